@@ -0,0 +1,164 @@
+//! Checking `assert` and `expect` statements.
+
+use crate::expr::*;
+use crate::explore::ReductionGraph;
+use crate::profile::Profile;
+use crate::reduce::{format_position, Position, RewriteRules, Strategy};
+use crate::symbol_table::SymbolTable;
+
+/// `assert a ->* b` gives up after visiting this many distinct terms
+pub const MAX_SEARCH_NODES: usize = 10_000;
+
+/// A subterm where the expected and actual terms differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub position: Position,
+    pub expected: Expression,
+    pub actual: Expression
+}
+
+/// Why an assertion did not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The term has a different normal form than expected
+    Mismatch { actual: Expression, differences: Vec<Difference> },
+    /// The term did not reach a normal form within the step limit
+    StepLimit(usize),
+    /// The search gave up before visiting every reachable term
+    SearchLimit(usize),
+    /// `assert a -/->* b`, but `b` is reachable
+    Reachable
+}
+
+impl Failure {
+
+    /// A human readable explanation, one line per entry.
+    pub fn describe(&self, symbols: &SymbolTable) -> Vec<String> {
+        match self {
+            Failure::Mismatch { actual, differences } => {
+                let mut lines = vec![format!("actual: {}", actual.bind(symbols))];
+                for d in differences {
+                    lines.push(format!(
+                        "at {}: expected {}, found {}",
+                        format_position(&d.position),
+                        d.expected.bind(symbols),
+                        d.actual.bind(symbols)
+                    ));
+                }
+                lines
+            },
+            Failure::StepLimit(steps) => vec![format!("no normal form after {} steps", steps)],
+            Failure::SearchLimit(nodes) => vec![format!("gave up after visiting {} terms", nodes)],
+            Failure::Reachable => vec!["the term is reachable".to_owned()]
+        }
+    }
+
+}
+
+/// Check an assertion against `rules`. Other statements always hold.
+/// Normalizing the term of an `expect` is counted in `profile`, if given.
+pub fn check(statement: &Statement, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, max_steps: usize, profile: Option<&mut Profile>) -> Result<(), Failure> {
+    match statement {
+        Statement::Expect(term, expected) => {
+            let derivation = term.derive_profiled(rules, symbols, max_steps, strategy, profile);
+            if derivation.truncated {
+                return Err(Failure::StepLimit(max_steps));
+            }
+            compare(expected, derivation.result())
+        },
+        Statement::Reaches(term, target) => {
+            let graph = ReductionGraph::explore(term, rules, symbols, MAX_SEARCH_NODES);
+            if graph.contains(target) {
+                return Ok(());
+            }
+            if !graph.complete {
+                return Err(Failure::SearchLimit(MAX_SEARCH_NODES));
+            }
+            // Show how the normal form the strategy picks differs from the target
+            let derivation = term.derive_with(rules, symbols, max_steps, strategy);
+            compare(target, derivation.result())
+        },
+        Statement::NotReaches(term, target) => {
+            let graph = ReductionGraph::explore(term, rules, symbols, MAX_SEARCH_NODES);
+            if graph.contains(target) {
+                Err(Failure::Reachable)
+            } else if !graph.complete {
+                Err(Failure::SearchLimit(MAX_SEARCH_NODES))
+            } else {
+                Ok(())
+            }
+        },
+        _ => Ok(())
+    }
+}
+
+fn compare(expected: &Expression, actual: &Expression) -> Result<(), Failure> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Failure::Mismatch { actual: actual.clone(), differences: diff(expected, actual) })
+    }
+}
+
+/// The subterms where `expected` and `actual` differ, descending
+/// into parentheses as long as both sides have the same shape.
+pub fn diff(expected: &Expression, actual: &Expression) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_terms(&expected.0, &actual.0, &mut vec![], &mut differences);
+    differences
+}
+
+fn diff_terms(expected: &[Terminal], actual: &[Terminal], position: &mut Position, out: &mut Vec<Difference>) {
+    if expected.len() != actual.len() {
+        out.push(Difference {
+            position: position.clone(),
+            expected: Expression(expected.to_vec()),
+            actual: Expression(actual.to_vec())
+        });
+        return;
+    }
+    for (idx, (e, a)) in expected.iter().zip(actual).enumerate() {
+        if e == a {
+            continue;
+        }
+        position.push(idx);
+        match (e, a) {
+            (Terminal::Parentheses(e), Terminal::Parentheses(a)) => diff_terms(&e.0, &a.0, position, out),
+            _ => out.push(Difference {
+                position: position.clone(),
+                expected: Expression(vec![e.clone()]),
+                actual: Expression(vec![a.clone()])
+            })
+        }
+        position.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::reduce::RewriteRule;
+
+    #[test]
+    fn test_diff() {
+        let mut symbols = SymbolTable::new();
+        let differences = diff(&expr!(symbols S (S 0)), &expr!(symbols S (S 1)));
+        assert_eq!(differences, vec![Difference { position: vec![1, 1], expected: expr!(symbols 0), actual: expr!(symbols 1) }]);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        rules.add(RewriteRule::new(expr!(symbols a), expr!(symbols b)));
+        rules.add(RewriteRule::new(expr!(symbols a), expr!(symbols c)));
+        let check = |s: &Statement, symbols: &SymbolTable| check(s, &rules, symbols, Strategy::Innermost, 100, None);
+        assert_eq!(check(&Statement::Expect(expr!(symbols a), expr!(symbols b)), &symbols), Ok(()));
+        assert!(check(&Statement::Expect(expr!(symbols a), expr!(symbols c)), &symbols).is_err());
+        // `c` is only reachable when the second rule is chosen
+        assert_eq!(check(&Statement::Reaches(expr!(symbols a), expr!(symbols c)), &symbols), Ok(()));
+        assert_eq!(check(&Statement::NotReaches(expr!(symbols a), expr!(symbols c)), &symbols), Err(Failure::Reachable));
+    }
+
+}
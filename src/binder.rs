@@ -0,0 +1,162 @@
+//! Operations on binders.
+//!
+//! A binder `\x. body` stores its body with every occurrence of `x`
+//! replaced by a de Bruijn index: `Bound(0)` refers to the innermost
+//! enclosing binder, `Bound(1)` to the one around it and so on.
+//! Alpha-equivalent terms are therefore structurally equal, and
+//! substitution can not capture variables.
+
+use std::ops::Range;
+
+use crate::expr::*;
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// Add `delta` to every index of `term` that refers to a binder
+/// outside of it, i.e. is at least `cutoff` plus the binders crossed.
+pub fn shift(term: &Terminal, delta: isize, cutoff: usize) -> Terminal {
+    match term {
+        Terminal::Bound(idx) if *idx >= cutoff => Terminal::Bound((*idx as isize + delta) as usize),
+        Terminal::Binder(hint, body) => Terminal::Binder(*hint, Box::new(shift(body, delta, cutoff + 1))),
+        Terminal::Parentheses(e) => Terminal::Parentheses(Expression(e.0.iter().map(|t| shift(t, delta, cutoff)).collect())),
+        Terminal::Substitution(v, arg) => Terminal::Substitution(*v, Box::new(shift(arg, delta, cutoff))),
+        _ => term.clone()
+    }
+}
+
+/// Replace the variable of the binder around `body` with `arg`,
+/// for the body of a binder that is being removed.
+pub fn open(body: &Terminal, arg: &Terminal) -> Terminal {
+    open_at(body, 0, arg)
+}
+
+fn open_at(term: &Terminal, depth: usize, arg: &Terminal) -> Terminal {
+    match term {
+        Terminal::Bound(idx) if *idx == depth => shift(arg, depth as isize, 0),
+        // One binder less lies between these and the binder they refer to
+        Terminal::Bound(idx) if *idx > depth => Terminal::Bound(idx - 1),
+        Terminal::Binder(hint, body) => Terminal::Binder(*hint, Box::new(open_at(body, depth + 1, arg))),
+        Terminal::Parentheses(e) => Terminal::Parentheses(Expression(e.0.iter().map(|t| open_at(t, depth, arg)).collect())),
+        Terminal::Substitution(v, inner) => Terminal::Substitution(*v, Box::new(open_at(inner, depth, arg))),
+        _ => term.clone()
+    }
+}
+
+/// Turn the free occurrences of the symbol `name` into indices,
+/// for the body of a new binder of `name`.
+pub fn abstract_name(term: &Terminal, name: SymbolHandle) -> Terminal {
+    abstract_at(term, name, 0)
+}
+
+fn abstract_at(term: &Terminal, name: SymbolHandle, depth: usize) -> Terminal {
+    match term {
+        Terminal::Symbol(s) if *s == name => Terminal::Bound(depth),
+        Terminal::Binder(hint, body) => Terminal::Binder(*hint, Box::new(abstract_at(body, name, depth + 1))),
+        Terminal::Parentheses(e) => Terminal::Parentheses(Expression(e.0.iter().map(|t| abstract_at(t, name, depth)).collect())),
+        Terminal::Substitution(v, arg) => Terminal::Substitution(*v, Box::new(abstract_at(arg, name, depth))),
+        _ => term.clone()
+    }
+}
+
+/// True if `term` refers to one of the `binders` around it,
+/// counted outwards from the term the way its indices are.
+pub fn refers_to(term: &Terminal, binders: Range<usize>) -> bool {
+    match term {
+        Terminal::Bound(idx) => binders.contains(idx),
+        Terminal::Binder(_, body) => refers_to(body, binders.start + 1..binders.end + 1),
+        Terminal::Parentheses(e) => e.0.iter().any(|t| refers_to(t, binders.clone())),
+        Terminal::Substitution(_, arg) => refers_to(arg, binders),
+        _ => false
+    }
+}
+
+/// The fewest binders any use of the variable `var` in `terms` is under.
+/// The body of `$b[t]` counts as under the binder it is taken from.
+pub fn shallowest_use(terms: &[Terminal], var: SymbolHandle) -> Option<usize> {
+    shallowest_use_at(terms, var, 0)
+}
+
+fn shallowest_use_at(terms: &[Terminal], var: SymbolHandle, depth: usize) -> Option<usize> {
+    terms.iter()
+        .filter_map(|term| match term {
+            Terminal::Variable(v, _, _) if *v == var => Some(depth),
+            Terminal::Substitution(v, arg) => {
+                let body = (*v == var).then_some(depth + 1);
+                let arg = shallowest_use_at(std::slice::from_ref(arg.as_ref()), var, depth);
+                body.into_iter().chain(arg).min()
+            },
+            Terminal::Parentheses(e) => shallowest_use_at(&e.0, var, depth),
+            Terminal::Binder(_, body) => shallowest_use_at(std::slice::from_ref(body.as_ref()), var, depth + 1),
+            _ => None
+        })
+        .min()
+}
+
+/// The body of a binder as an expression, the way it was written.
+pub fn body_expression(body: &Terminal) -> Expression {
+    match body {
+        Terminal::Parentheses(e) => e.clone(),
+        _ => Expression(vec![body.clone()])
+    }
+}
+
+/// The inverse of `body_expression`.
+pub fn expression_body(mut expr: Expression) -> Terminal {
+    if expr.0.len() == 1 {
+        expr.0.pop().unwrap()
+    } else {
+        Terminal::Parentheses(expr)
+    }
+}
+
+/// True if a symbol called `name` occurs anywhere in `term`.
+pub fn mentions(term: &Terminal, name: &str, symbols: &SymbolTable) -> bool {
+    match term {
+        Terminal::Symbol(s) => symbols.lookup(*s) == name,
+        Terminal::Binder(_, body) => mentions(body, name, symbols),
+        Terminal::Parentheses(e) => e.0.iter().any(|t| mentions(t, name, symbols)),
+        Terminal::Substitution(_, arg) => mentions(arg, name, symbols),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::trs::Trs;
+
+    #[test]
+    fn test_open_avoids_capture() {
+        let mut symbols = SymbolTable::new();
+        let x = Hint(symbols.handle("x"));
+        // The body of \y. \x. y x, opened with a free x
+        let body = Terminal::Binder(x, Box::new(Terminal::Parentheses(Expression(vec![Terminal::Bound(1), Terminal::Bound(0)]))));
+        let free_x = Terminal::Symbol(symbols.handle("x"));
+        assert_eq!(
+            open(&body, &free_x),
+            Terminal::Binder(x, Box::new(Terminal::Parentheses(Expression(vec![free_x.clone(), Terminal::Bound(0)]))))
+        );
+    }
+
+    #[test]
+    fn test_beta_renames_bound_variables() {
+        let mut trs = Trs::from_source("(\\x. $b) $a -> $b[$a]").unwrap();
+        let derivation = trs.derive("(\\x. \\y. x) y", 10).unwrap();
+        assert_eq!(derivation.result().bind(trs.symbols()).to_string(), "\\y'. y");
+        // Alpha-equivalent terms are equal
+        assert_eq!(trs.derive("\\a. a", 0).unwrap().start, trs.derive("\\b. b", 0).unwrap().start);
+    }
+
+    #[test]
+    fn test_bound_terms_stay_under_their_binder() {
+        let mut trs = Trs::from_source("[eta] \\x. $f x -> $f").unwrap();
+        let derivation = trs.derive("\\x. g x", 10).unwrap();
+        assert_eq!(derivation.result().bind(trs.symbols()).to_string(), "g");
+        // The x in x x would be left without its binder
+        let derivation = trs.derive("\\x. x x", 10).unwrap();
+        assert!(derivation.steps.is_empty());
+        let derivation = trs.derive("\\y. \\x. y x", 10).unwrap();
+        assert_eq!(derivation.result().bind(trs.symbols()).to_string(), "\\y. y");
+    }
+
+}
@@ -0,0 +1,364 @@
+//! Sufficient completeness: whether the rules for each defined symbol
+//! handle every combination of constructors it can be applied to.
+//!
+//! A symbol is defined when it is at the root of a left side, like `+`
+//! in `$x + 0 -> $x`, and a constructor otherwise, like `S` and `0`.
+//! There are no declared sorts, so two positions are assumed to hold
+//! the same sort when some rule puts the same variable or term in both.
+//! That way `$x + $y` is only expected to handle `0` and `S _`, not
+//! `true`.
+
+use std::collections::HashMap;
+
+use crate::expr::*;
+use crate::reduce::RewriteRules;
+use crate::signature::{Shape, Signature};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// A constant or the index of a shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Head {
+    Constant(SymbolHandle),
+    Shape(usize)
+}
+
+/// The terms a rule argument matches, as far as constructors go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Any,
+    Constructor(Head, Vec<Pattern>)
+}
+
+/// Arguments of a defined shape that no rule handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingCase {
+    pub shape: Shape,
+    pub args: Vec<Pattern>
+}
+
+/// A position that holds terms of some sort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Position {
+    Result(Head),
+    Argument(usize, usize),
+    Rule(usize)
+}
+
+pub struct Analysis {
+    signature: Signature,
+    /// The shapes of the signature and of the left sides
+    shapes: Vec<Shape>,
+    defined: Vec<Head>,
+    positions: HashMap<Position, usize>,
+    // Union-find over the positions, equal roots hold the same sort
+    parents: Vec<usize>
+}
+
+impl Analysis {
+
+    pub fn new(rules: &RewriteRules, signature: Signature) -> Self {
+        let mut shapes = signature.shapes.clone();
+        let mut analysis = Analysis { signature, shapes: vec![], defined: vec![], positions: HashMap::new(), parents: vec![] };
+        for rule in rules.iter() {
+            if let [_, _, ..] = rule.left.0.as_slice() {
+                let shape = analysis.signature.shape_of(&rule.left.0);
+                if !shapes.contains(&shape) {
+                    shapes.push(shape);
+                }
+            }
+        }
+        analysis.shapes = shapes;
+        for rule in rules.iter() {
+            if let Some(head) = analysis.head(&as_terminal(&rule.left)) {
+                if !analysis.defined.contains(&head) {
+                    analysis.defined.push(head);
+                }
+            }
+        }
+        for (idx, rule) in rules.iter().enumerate() {
+            let mut vars = HashMap::new();
+            let root = analysis.position(Position::Rule(idx));
+            analysis.visit(&as_terminal(&rule.left), root, &mut vars);
+            analysis.visit(&as_terminal(&rule.right), root, &mut vars);
+        }
+        analysis
+    }
+
+    /// Every constructor pattern that no rule handles, for each defined shape.
+    pub fn missing_cases(&self, rules: &RewriteRules) -> Vec<MissingCase> {
+        let mut missing = vec![];
+        for head in &self.defined {
+            let Head::Shape(idx) = *head else { continue };
+            let shape = &self.shapes[idx];
+            let mut rows = vec![];
+            let mut usable = true;
+            for rule in rules.iter() {
+                if rule.left.0.len() < 2 || self.signature.shape_of(&rule.left.0) != *shape {
+                    continue;
+                }
+                match self.row(rule.left.0.as_slice()).filter(|_| rule.constraints.is_empty()) {
+                    Some(row) => rows.push(row),
+                    None => usable = false
+                }
+            }
+            // A rule that can't be expressed with constructors might handle any case
+            if !usable {
+                continue;
+            }
+            let columns: Vec<usize> = (0..shape.arity()).map(|arg| self.sort(Position::Argument(idx, arg))).collect();
+            for args in self.missing(&rows, &columns) {
+                missing.push(MissingCase { shape: shape.clone(), args });
+            }
+        }
+        missing
+    }
+
+    /// The constant or shape of `term`. Shapes without a symbol, like
+    /// `_ _`, are left out, they hold terms of any sort together.
+    fn head(&self, term: &Terminal) -> Option<Head> {
+        match term {
+            Terminal::Symbol(s) => Some(Head::Constant(*s)),
+            Terminal::Parentheses(e) => {
+                let shape = self.signature.shape_of(&e.0);
+                if shape.arity() == shape.0.len() {
+                    return None;
+                }
+                self.shapes.iter().position(|s| *s == shape).map(Head::Shape)
+            },
+            _ => None
+        }
+    }
+
+    fn arity(&self, head: Head) -> usize {
+        match head {
+            Head::Constant(_) => 0,
+            Head::Shape(idx) => self.shapes[idx].arity()
+        }
+    }
+
+    fn is_constructor(&self, head: &Head) -> bool {
+        !self.defined.contains(head)
+    }
+
+    /// The constructors of the sort with root `sort`.
+    fn constructors(&self, sort: usize) -> Vec<Head> {
+        let constants = self.signature.constants.iter().map(|c| Head::Constant(*c));
+        let shapes = (0..self.shapes.len()).map(Head::Shape);
+        constants.chain(shapes)
+            .filter(|head| self.is_constructor(head))
+            .filter(|head| self.positions.contains_key(&Position::Result(*head)) && self.sort(Position::Result(*head)) == sort)
+            .collect()
+    }
+
+    fn position(&mut self, position: Position) -> usize {
+        let next = self.parents.len();
+        let id = *self.positions.entry(position).or_insert(next);
+        if id == next {
+            self.parents.push(id);
+        }
+        id
+    }
+
+    fn find(&self, mut id: usize) -> usize {
+        while self.parents[id] != id {
+            id = self.parents[id];
+        }
+        id
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a] = b;
+    }
+
+    /// The sort of `position`, positions no rule mentions have a sort of their own.
+    fn sort(&self, position: Position) -> usize {
+        match self.positions.get(&position) {
+            Some(id) => self.find(*id),
+            None => usize::MAX
+        }
+    }
+
+    /// Record that `term` stands at `at`.
+    fn visit(&mut self, term: &Terminal, at: usize, vars: &mut HashMap<SymbolHandle, usize>) {
+        match term {
+            Terminal::Variable(v, _, _) => match vars.get(v) {
+                Some(other) => self.union(at, *other),
+                None => {
+                    vars.insert(*v, at);
+                }
+            },
+            Terminal::Symbol(_) | Terminal::Parentheses(_) => {
+                let Some(head) = self.head(term) else { return };
+                let result = self.position(Position::Result(head));
+                self.union(at, result);
+                if let (Terminal::Parentheses(e), Head::Shape(idx)) = (term, head) {
+                    let args = e.0.iter().zip(&self.shapes[idx].0).filter(|(_, slot)| slot.is_none()).map(|(t, _)| t.clone());
+                    for (arg, t) in args.collect::<Vec<_>>().iter().enumerate() {
+                        let position = self.position(Position::Argument(idx, arg));
+                        self.visit(t, position, vars);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// The arguments of a left side as patterns, or None if it
+    /// repeats or guards variables or matches a defined symbol.
+    fn row(&self, left: &[Terminal]) -> Option<Vec<Pattern>> {
+        let shape = self.signature.shape_of(left);
+        let mut seen = vec![];
+        left.iter().zip(&shape.0)
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(term, _)| self.pattern(term, &mut seen))
+            .collect()
+    }
+
+    fn pattern(&self, term: &Terminal, seen: &mut Vec<SymbolHandle>) -> Option<Pattern> {
+        match term {
+            Terminal::Variable(v, VariableKind::Any, None) if !seen.contains(v) => {
+                seen.push(*v);
+                Some(Pattern::Any)
+            },
+            Terminal::Symbol(_) | Terminal::Parentheses(_) => {
+                let head = self.head(term).filter(|head| self.is_constructor(head))?;
+                let args = match (term, head) {
+                    (Terminal::Parentheses(e), Head::Shape(idx)) => self.row(&e.0).filter(|_| self.signature.shape_of(&e.0) == self.shapes[idx])?,
+                    _ => vec![]
+                };
+                Some(Pattern::Constructor(head, args))
+            },
+            _ => None
+        }
+    }
+
+    /// The argument lists of sorts `columns` that none of `rows` matches.
+    fn missing(&self, rows: &[Vec<Pattern>], columns: &[usize]) -> Vec<Vec<Pattern>> {
+        let Some((&sort, rest)) = columns.split_first() else {
+            return if rows.is_empty() { vec![vec![]] } else { vec![] };
+        };
+        let constructors = self.constructors(sort);
+        let mut heads = vec![];
+        for row in rows {
+            if let Pattern::Constructor(head, _) = &row[0] {
+                if !heads.contains(head) {
+                    heads.push(*head);
+                }
+            }
+        }
+        let mut out = vec![];
+        if !heads.is_empty() && constructors.iter().all(|c| heads.contains(c)) {
+            // Every constructor has a rule, look for what's missing underneath
+            for head in heads {
+                let arity = self.arity(head);
+                let specialized: Vec<Vec<Pattern>> = rows.iter()
+                    .filter_map(|row| match &row[0] {
+                        Pattern::Constructor(h, args) if *h == head => Some([args.as_slice(), &row[1..]].concat()),
+                        Pattern::Any => Some([vec![Pattern::Any; arity].as_slice(), &row[1..]].concat()),
+                        _ => None
+                    })
+                    .collect();
+                let mut sorts = match head {
+                    Head::Shape(idx) => (0..arity).map(|arg| self.sort(Position::Argument(idx, arg))).collect(),
+                    Head::Constant(_) => vec![]
+                };
+                sorts.extend_from_slice(rest);
+                for mut args in self.missing(&specialized, &sorts) {
+                    let others = args.split_off(arity);
+                    out.push([vec![Pattern::Constructor(head, args)], others].concat());
+                }
+            }
+        } else {
+            let default: Vec<Vec<Pattern>> = rows.iter()
+                .filter(|row| row[0] == Pattern::Any)
+                .map(|row| row[1..].to_vec())
+                .collect();
+            let firsts: Vec<Pattern> = if heads.is_empty() {
+                vec![Pattern::Any]
+            } else {
+                constructors.into_iter()
+                    .filter(|c| !heads.contains(c))
+                    .map(|c| Pattern::Constructor(c, vec![Pattern::Any; self.arity(c)]))
+                    .collect()
+            };
+            for others in self.missing(&default, rest) {
+                for first in &firsts {
+                    out.push([vec![first.clone()], others.clone()].concat());
+                }
+            }
+        }
+        out
+    }
+
+    /// A missing case as a left side, with fresh variables for the arguments that don't matter.
+    pub fn describe(&self, case: &MissingCase, symbols: &SymbolTable) -> String {
+        let mut vars = 0;
+        self.write_shape(&case.shape, &case.args, symbols, &mut vars)
+    }
+
+    fn write_shape(&self, shape: &Shape, args: &[Pattern], symbols: &SymbolTable, vars: &mut usize) -> String {
+        let mut args = args.iter();
+        let parts: Vec<String> = shape.0.iter()
+            .map(|slot| match slot {
+                Some(s) => symbols.lookup(*s).to_owned(),
+                None => self.write_pattern(args.next().unwrap_or(&Pattern::Any), symbols, vars)
+            })
+            .collect();
+        parts.join(" ")
+    }
+
+    fn write_pattern(&self, pattern: &Pattern, symbols: &SymbolTable, vars: &mut usize) -> String {
+        match pattern {
+            Pattern::Any => {
+                const NAMES: [&str; 4] = ["x", "y", "z", "w"];
+                *vars += 1;
+                match NAMES.get(*vars - 1) {
+                    Some(name) => format!("${}", name),
+                    None => format!("$x{}", *vars - NAMES.len())
+                }
+            },
+            Pattern::Constructor(Head::Constant(c), _) => symbols.lookup(*c).to_owned(),
+            Pattern::Constructor(Head::Shape(idx), args) => format!("({})", self.write_shape(&self.shapes[*idx], args, symbols, vars))
+        }
+    }
+
+}
+
+fn as_terminal(expr: &Expression) -> Terminal {
+    match expr.0.as_slice() {
+        [term] => term.clone(),
+        _ => Terminal::Parentheses(expr.clone())
+    }
+}
+
+/// The missing cases of `rules`, described as left sides.
+pub fn check(rules: &RewriteRules, signature: Signature, symbols: &SymbolTable) -> Vec<String> {
+    let analysis = Analysis::new(rules, signature);
+    analysis.missing_cases(rules).iter().map(|case| analysis.describe(case, symbols)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::trs::Trs;
+    use super::*;
+
+    fn missing(src: &str) -> Vec<String> {
+        let trs = Trs::from_source(src).unwrap();
+        check(trs.rules(), Signature::infer(trs.rules()), trs.symbols())
+    }
+
+    #[test]
+    fn test_complete() {
+        let src = "$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)\nnot true -> false\nnot false -> true";
+        assert!(missing(src).is_empty());
+    }
+
+    #[test]
+    fn test_missing_cases() {
+        let src = "$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)\n0 * $x -> 0\n(S (S $x)) * $y -> $y + ($x * $y)\nnot true -> false\ntrue and $x -> $x\nfalse and $x -> false";
+        assert_eq!(missing(src), vec!["(S 0) * $x", "not false"]);
+    }
+
+}
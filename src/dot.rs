@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use crate::binder::body_expression;
+use crate::expr::*;
+use crate::explore::ReductionGraph;
+use crate::reduce::{format_position, Derivation, RewriteRules};
+use crate::symbol_table::SymbolTable;
+
+// Writing to a String never fails, so the results of
+// `write!` are ignored throughout this module.
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Edges are labeled with the rule label, or the rule itself if it has none.
+fn rule_label(rule: usize, rules: &RewriteRules, symbols: &SymbolTable) -> String {
+    let rule = rules.get(rule);
+    match rule.label {
+        Some(label) => symbols.lookup(label).to_owned(),
+        None => format!("{} -> {}", rule.left.bind(symbols), rule.right.bind(symbols))
+    }
+}
+
+/// The syntax tree of an expression as a DOT digraph.
+pub fn tree_to_dot(expr: &Expression, symbols: &SymbolTable) -> String {
+    let mut out = String::from("digraph term {\n    node [shape=plaintext];\n    n0 [label=\"·\"];\n");
+    let mut next = 1;
+    write_tree(&mut out, symbols, &expr.0, 0, &mut next);
+    out.push_str("}\n");
+    out
+}
+
+fn write_tree(out: &mut String, symbols: &SymbolTable, terms: &[Terminal], parent: usize, next: &mut usize) {
+    for term in terms {
+        let id = *next;
+        *next += 1;
+        let label = match term {
+            Terminal::Parentheses(_) => "( )".to_owned(),
+            Terminal::Binder(hint, _) => format!("\\{}.", symbols.lookup(hint.0)),
+            _ => format!("{}", term.bind(symbols))
+        };
+        let _ = writeln!(out, "    n{} [label=\"{}\"];", id, escape(&label));
+        let _ = writeln!(out, "    n{} -> n{};", parent, id);
+        match term {
+            Terminal::Parentheses(e) => write_tree(out, symbols, &e.0, id, next),
+            Terminal::Binder(_, body) => write_tree(out, symbols, &body_expression(body).0, id, next),
+            _ => ()
+        }
+    }
+}
+
+/// A single reduction path, one node per term.
+pub fn derivation_to_dot(derivation: &Derivation, rules: &RewriteRules, symbols: &SymbolTable) -> String {
+    let mut out = String::from("digraph derivation {\n    node [shape=box];\n");
+    let _ = writeln!(out, "    n0 [label=\"{}\"];", escape(&derivation.start.bind(symbols).to_string()));
+    for (idx, step) in derivation.steps.iter().enumerate() {
+        let _ = writeln!(out, "    n{} [label=\"{}\"];", idx + 1, escape(&step.term.bind(symbols).to_string()));
+        let _ = writeln!(
+            out,
+            "    n{} -> n{} [label=\"{} @ {}\"];",
+            idx,
+            idx + 1,
+            escape(&rule_label(step.rule, rules, symbols)),
+            format_position(&step.position)
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Every explored term and every rewrite between them.
+/// Normal forms are drawn with a double border, terms whose
+/// rewrites were cut off at the node limit with a dashed one.
+pub fn graph_to_dot(graph: &ReductionGraph, rules: &RewriteRules, symbols: &SymbolTable) -> String {
+    let mut out = String::from("digraph reductions {\n    node [shape=box];\n");
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        let mut attrs = format!("label=\"{}\"", escape(&node.bind(symbols).to_string()));
+        let mut style = vec![];
+        if idx == 0 {
+            style.push("bold");
+        }
+        if graph.unexpanded.contains(&idx) {
+            style.push("dashed");
+        }
+        if !style.is_empty() {
+            let _ = write!(attrs, ", style=\"{}\"", style.join(","));
+        }
+        if graph.is_normal_form(idx) {
+            attrs.push_str(", peripheries=2");
+        }
+        let _ = writeln!(out, "    n{} [{}];", idx, attrs);
+    }
+    for (from, to, rewrite) in &graph.edges {
+        let _ = writeln!(
+            out,
+            "    n{} -> n{} [label=\"{} @ {}\"];",
+            from,
+            to,
+            escape(&rule_label(rewrite.rule, rules, symbols)),
+            format_position(&rewrite.position)
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::reduce::RewriteRule;
+
+    #[test]
+    fn test_tree_to_dot() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols S (S 0));
+        assert_eq!(
+            tree_to_dot(&e, &symbols),
+            "digraph term {\n    node [shape=plaintext];\n    n0 [label=\"·\"];\n\
+             \x20   n1 [label=\"S\"];\n    n0 -> n1;\n\
+             \x20   n2 [label=\"( )\"];\n    n0 -> n2;\n\
+             \x20   n3 [label=\"S\"];\n    n2 -> n3;\n\
+             \x20   n4 [label=\"0\"];\n    n2 -> n4;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_derivation_to_dot() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let label = symbols.handle("define-one");
+        rules.add(RewriteRule::labeled(Some(label), expr!(symbols 1), expr!(symbols S 0)));
+        let derivation = expr!(symbols S 1).derive(&rules, &symbols, 10);
+        assert_eq!(
+            derivation_to_dot(&derivation, &rules, &symbols),
+            "digraph derivation {\n    node [shape=box];\n    n0 [label=\"S 1\"];\n\
+             \x20   n1 [label=\"S (S 0)\"];\n    n0 -> n1 [label=\"define-one @ 1\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_graph_to_dot_limit() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let label = symbols.handle("grow");
+        rules.add(RewriteRule::labeled(Some(label), expr!(symbols S [x]), expr!(symbols S (S [x]))));
+        let graph = ReductionGraph::explore(&expr!(symbols S 0), &rules, &symbols, 2);
+        assert_eq!(
+            graph_to_dot(&graph, &rules, &symbols),
+            "digraph reductions {\n    node [shape=box];\n    n0 [label=\"S 0\", style=\"bold\"];\n\
+             \x20   n1 [label=\"S (S 0)\", style=\"dashed\"];\n    n0 -> n1 [label=\"grow @ ε\"];\n}\n"
+        );
+    }
+
+}
@@ -0,0 +1,110 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::expr::*;
+use crate::reduce::{Rewrite, RewriteRules};
+use crate::symbol_table::SymbolTable;
+
+/// The terms reachable from a starting term when every redex is
+/// rewritten, not just the one `reduce_once` would choose.
+pub struct ReductionGraph {
+    /// Every term visited, the starting term is always node 0
+    pub nodes: Vec<Expression>,
+    /// Rewrites from one node to another
+    pub edges: Vec<(usize, usize, Rewrite)>,
+    /// False if exploration stopped at the node limit
+    /// before every reachable term was visited
+    pub complete: bool,
+    /// Nodes with rewrites to terms that were dropped at the node limit,
+    /// so their edges are incomplete
+    pub unexpanded: Vec<usize>
+}
+
+impl ReductionGraph {
+
+    /// Explore the terms reachable from `start` breadth first,
+    /// visiting at most `max_nodes` distinct terms.
+    pub fn explore(start: &Expression, rules: &RewriteRules, symbols: &SymbolTable, max_nodes: usize) -> Self {
+        let mut graph = ReductionGraph { nodes: vec![start.clone()], edges: vec![], complete: true, unexpanded: vec![] };
+        let mut index = HashMap::new();
+        index.insert(start.clone(), 0);
+        let mut queue = VecDeque::from([0]);
+        while let Some(from) = queue.pop_front() {
+            for rewrite in rules.find_redexes(&graph.nodes[from], symbols) {
+                let mut term = graph.nodes[from].clone();
+                if !term.apply(&rewrite, rules, symbols) {
+                    continue;
+                }
+                let to = match index.get(&term) {
+                    Some(to) => *to,
+                    None => {
+                        if graph.nodes.len() >= max_nodes {
+                            graph.complete = false;
+                            if graph.unexpanded.last() != Some(&from) {
+                                graph.unexpanded.push(from);
+                            }
+                            continue;
+                        }
+                        graph.nodes.push(term.clone());
+                        index.insert(term, graph.nodes.len() - 1);
+                        queue.push_back(graph.nodes.len() - 1);
+                        graph.nodes.len() - 1
+                    }
+                };
+                graph.edges.push((from, to, rewrite));
+            }
+        }
+        graph
+    }
+
+    /// True if `idx` has no outgoing rewrites, not even to terms past the node limit.
+    pub fn is_normal_form(&self, idx: usize) -> bool {
+        !self.unexpanded.contains(&idx) && !self.edges.iter().any(|(from, _, _)| *from == idx)
+    }
+
+    /// Terms with no outgoing rewrites.
+    pub fn normal_forms(&self) -> impl Iterator<Item = &Expression> {
+        self.nodes.iter()
+            .enumerate()
+            .filter(|(idx, _)| self.is_normal_form(*idx))
+            .map(|(_, node)| node)
+    }
+
+    pub fn contains(&self, expr: &Expression) -> bool {
+        self.nodes.contains(expr)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::reduce::RewriteRule;
+
+    #[test]
+    fn test_explore_branches() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        rules.add(RewriteRule::new(expr!(symbols a), expr!(symbols b)));
+        rules.add(RewriteRule::new(expr!(symbols a), expr!(symbols c)));
+        let graph = ReductionGraph::explore(&expr!(symbols a a), &rules, &symbols, 100);
+        assert!(graph.complete);
+        // a a, b a, c a, a b, a c, b b, b c, c b, c c
+        assert_eq!(graph.nodes.len(), 9);
+        assert_eq!(graph.normal_forms().count(), 4);
+    }
+
+    #[test]
+    fn test_explore_limit() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        rules.add(RewriteRule::new(expr!(symbols S [x]), expr!(symbols S (S [x]))));
+        let graph = ReductionGraph::explore(&expr!(symbols S 0), &rules, &symbols, 10);
+        assert!(!graph.complete);
+        assert_eq!(graph.nodes.len(), 10);
+        // The last term still rewrites, its successor was just never visited
+        assert_eq!(graph.unexpanded, vec![9]);
+        assert_eq!(graph.normal_forms().count(), 0);
+    }
+
+}
@@ -1,28 +1,133 @@
-use crate::symbol_table::SymbolHandle;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+use crate::reduce::ReplacementMap;
+use crate::signature::Signature;
+use crate::strategy::Combinator;
+use crate::symbol_table::{NamePattern, SymbolHandle, SymbolTable};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
 pub enum VariableKind {
     Any,
     Distinct
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum Terminal {
-    // $n $x $abc
-    Variable(SymbolHandle, VariableKind),
+    // $n $x $abc $x:int
+    Variable(SymbolHandle, VariableKind, Option<Box<Guard>>),
     // 0 1 + abc x
     Symbol(SymbolHandle),
     // (a + b) / 4
-    Parentheses(Expression)
+    Parentheses(Expression),
+    // \x. body, where occurrences of x in the body are `Bound`
+    Binder(Hint, Box<Terminal>),
+    // A variable bound by the binder this many binders out, 0 is the innermost
+    Bound(usize),
+    // $b[t], the body bound to $b with its binder's variable replaced by t
+    Substitution(SymbolHandle, Box<Terminal>)
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+/// A condition on the terms a variable matches.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Guard {
+    /// `$x:sym`, a single symbol
+    Symbol,
+    /// `$x:paren`, a parenthesized term
+    Compound,
+    /// `$x:int` or `$x:/[a-z]+/`, a symbol whose name matches
+    Name(NamePattern),
+    /// `$x in {a, b, c}`, one of the listed symbols
+    OneOf(Vec<SymbolHandle>)
+}
+
+/// The name a binder was written with, only used for printing.
+/// Hints never affect equality, so alpha-equivalent terms are equal.
+#[derive(Debug, Clone, Copy)]
+pub struct Hint(pub SymbolHandle);
+
+impl PartialEq for Hint {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Hint {}
+
+impl Hash for Hint {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+/// A sequence of terms. Parsing, interpolation and rewriting keep
+/// expressions canonical, see `without_redundant_parens`, so that
+/// `(a)`, `((a))` and `a` are the same term.
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub struct Expression(pub Vec<Terminal>);
 
+impl Expression {
+
+    /// True for the lone `?` on the right side of a query like `x + 0 -> ?`
+    pub fn is_query(&self, symbols: &SymbolTable) -> bool {
+        matches!(self.0.as_slice(), [Terminal::Symbol(s)] if symbols.lookup(*s) == "?")
+    }
+
+    /// A copy without parentheses around a single terminal
+    /// or directly around another pair of parentheses.
+    pub fn without_redundant_parens(&self) -> Expression {
+        let mut terms = self.0.as_slice();
+        while let [Terminal::Parentheses(e)] = terms {
+            terms = &e.0;
+        }
+        Expression(terms.iter().map(Terminal::without_redundant_parens).collect())
+    }
+
+}
+
+impl Terminal {
+
+    pub fn without_redundant_parens(&self) -> Terminal {
+        match self {
+            Terminal::Parentheses(e) => {
+                let mut inner = e.without_redundant_parens();
+                if inner.0.len() == 1 {
+                    inner.0.pop().unwrap()
+                } else {
+                    Terminal::Parentheses(inner)
+                }
+            },
+            Terminal::Binder(hint, body) => Terminal::Binder(*hint, Box::new(body.without_redundant_parens())),
+            Terminal::Substitution(v, arg) => Terminal::Substitution(*v, Box::new(arg.without_redundant_parens())),
+            _ => self.clone()
+        }
+    }
+
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Statement {
-    // Define a new rewrite rule
-    Rewrite(Expression, Expression),
+    // Define a new rewrite rule, applied only where its constraints hold
+    Rewrite(Expression, Expression, Vec<Constraint>),
+    // assert a ->* b, b can be reached from a
+    Reaches(Expression, Expression),
+    // assert a -/->* b, b can not be reached from a
+    NotReaches(Expression, Expression),
+    // expect a -> ? b, a normalizes to b
+    Expect(Expression, Expression),
+    // proof a, starts a proof from a
+    Proof(Expression),
+    // = b {by label}, the next term of the current proof
+    Step(Expression, SymbolHandle),
+    // qed, ends the current proof
+    Qed,
+    // strategy name = s, names a strategy
+    Strategy(SymbolHandle, Combinator),
+    // apply s: a, rewrites a with the strategy s
+    Apply(Combinator, Expression),
+    // :lazy if ! then _ else _, only the arguments marked ! are rewritten
+    Lazy(ReplacementMap),
+    // forall $x $y: a ->* b, an assertion that holds whatever ground terms $x and $y are
+    Property(Vec<SymbolHandle>, Box<Statement>),
+    // signature 0, S _, _ + _, the terms properties are checked with
+    Signature(Signature),
     // An empty statement that does nothing
     Noop
 }
@@ -30,14 +135,24 @@ pub enum Statement {
 impl Statement {
 
     pub fn is_noop(&self) -> bool {
-        match self {
-            Statement::Noop => true,
-            _ => false
-        }
+        matches!(self, Statement::Noop)
+    }
+
+    pub fn is_assertion(&self) -> bool {
+        matches!(self, Statement::Reaches(..) | Statement::NotReaches(..) | Statement::Expect(..))
     }
 
 }
 
+/// A condition on the bindings of a rule, from its `where` clause.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Constraint {
+    /// `$x != $y`, the two sides are bound to different terms
+    NotEqual(Terminal, Terminal),
+    /// `$x !~ 0`, the term does not match the pattern
+    NotMatching(Terminal, Terminal)
+}
+
 #[derive(Debug)]
 pub struct Label(pub SymbolHandle);
 
@@ -51,18 +166,19 @@ pub struct Item {
     pub statement: Statement
 }
 
+#[cfg(test)]
 macro_rules! term {
     ($sym:ident ( $($t:tt)* ) ) => {
         Terminal::Parentheses( expr!($sym $($t)*) )
     };
     ($sym:ident [$s:ident]) => {
-        Terminal::Variable($sym.handle(stringify!($s)), VariableKind::Any)
+        Terminal::Variable($sym.handle(stringify!($s)), VariableKind::Any, None)
     };
     ($sym:ident [$s:literal]) => {
-        Terminal::Variable($sym.handle(&format!("{}", $s)), VariableKind::Any)
+        Terminal::Variable($sym.handle(&format!("{}", $s)), VariableKind::Any, None)
     };
     ($sym:ident [$s:tt]) => {
-        Terminal::Variable($sym.handle(stringify!($s)), VariableKind::Any)
+        Terminal::Variable($sym.handle(stringify!($s)), VariableKind::Any, None)
     };
     ($sym:ident $s:ident) => {
         Terminal::Symbol($sym.handle(stringify!($s)))
@@ -75,6 +191,7 @@ macro_rules! term {
     };
 }
 
+#[cfg(test)]
 macro_rules! expr {
     ($sym:ident $($t:tt)* ) => {
         Expression(vec![$( term!($sym $t) ),*])
@@ -85,13 +202,12 @@ macro_rules! expr {
 mod tests {
 
     use super::*;
-    use crate::symbol_table::SymbolTable;
 
     #[test]
     fn test_term_macro_var() {
         let mut symbols = SymbolTable::new();
-        assert_eq!(term!(symbols [x]), Terminal::Variable(symbols.handle("x"), VariableKind::Any));
-        assert_eq!(term!(symbols ["with space"]), Terminal::Variable(symbols.handle("with space"), VariableKind::Any));
+        assert_eq!(term!(symbols [x]), Terminal::Variable(symbols.handle("x"), VariableKind::Any, None));
+        assert_eq!(term!(symbols ["with space"]), Terminal::Variable(symbols.handle("with space"), VariableKind::Any, None));
     }
 
     #[test]
@@ -125,9 +241,9 @@ mod tests {
             expr!(symbols ([x] + [y]) / 2),
             Expression(vec![
                 Terminal::Parentheses(Expression(vec![
-                    Terminal::Variable(symbols.handle("x"), VariableKind::Any),
+                    Terminal::Variable(symbols.handle("x"), VariableKind::Any, None),
                     Terminal::Symbol(symbols.handle("+")),
-                    Terminal::Variable(symbols.handle("y"), VariableKind::Any)
+                    Terminal::Variable(symbols.handle("y"), VariableKind::Any, None)
                 ])),
                 Terminal::Symbol(symbols.handle("/")),
                 Terminal::Symbol(symbols.handle("2"))
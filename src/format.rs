@@ -0,0 +1,175 @@
+//! Canonical formatting of rule files.
+//!
+//! Consecutive rules form a block. Within a block labels are padded
+//! to a common width and the `->` arrows line up, the way `peano.txt`
+//! is laid out by hand. The `{by label}` citations of consecutive proof
+//! steps line up the same way. Blank lines and standalone comments end a
+//! block and are kept as they are.
+
+use crate::expr::*;
+use crate::parse::TryParse;
+use crate::symbol_table::SymbolTable;
+use crate::trs::SourceError;
+use crate::unparse::where_clause;
+
+struct Rule {
+    label: Option<String>,
+    left: String,
+    right: String,
+    comment: Option<String>
+}
+
+struct Step {
+    term: String,
+    label: String,
+    comment: Option<String>
+}
+
+enum Line {
+    Rule(Rule),
+    Step(Step),
+    // Anything that is not a rule is printed on its own
+    Other(String)
+}
+
+/// Format every line of `src`, failing if any line does not parse.
+/// Formatting the output again leaves it unchanged.
+pub fn format_source(src: &str) -> Result<String, Vec<SourceError>> {
+    let mut symbols = SymbolTable::new();
+    let mut lines = vec![];
+    let mut errors = vec![];
+    for (line, text) in src.lines().enumerate() {
+        match Item::parse_complete(text, &mut symbols) {
+            Ok(item) => lines.push(to_line(&item, &symbols)),
+            Err(error) => errors.push(SourceError { line, error })
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut out = String::new();
+    let mut block = vec![];
+    let mut steps = vec![];
+    for line in lines {
+        match line {
+            Line::Rule(rule) => {
+                write_steps(&mut out, &steps);
+                steps.clear();
+                block.push(rule);
+            },
+            Line::Step(step) => {
+                write_block(&mut out, &block);
+                block.clear();
+                steps.push(step);
+            },
+            Line::Other(text) => {
+                write_block(&mut out, &block);
+                block.clear();
+                write_steps(&mut out, &steps);
+                steps.clear();
+                out.push_str(text.trim_end());
+                out.push('\n');
+            }
+        }
+    }
+    write_block(&mut out, &block);
+    write_steps(&mut out, &steps);
+    Ok(out)
+}
+
+fn to_line(item: &Item, symbols: &SymbolTable) -> Line {
+    match &item.statement {
+        Statement::Rewrite(l, r, constraints) => Line::Rule(Rule {
+            label: item.label.as_ref().map(|label| label.bind(symbols).to_string()),
+            // Printed as parsed, parsing already drops the parentheses that don't matter
+            left: l.bind(symbols).to_string(),
+            right: format!("{}{}", r.bind(symbols), where_clause(constraints, symbols)),
+            comment: item.comment.as_ref().map(|comment| comment.to_string())
+        }),
+        Statement::Step(term, label) if item.label.is_none() => Line::Step(Step {
+            term: term.bind(symbols).to_string(),
+            label: symbols.lookup(*label).to_owned(),
+            comment: item.comment.as_ref().map(|comment| comment.to_string())
+        }),
+        _ => Line::Other(item.bind(symbols).to_string())
+    }
+}
+
+fn write_block(out: &mut String, block: &[Rule]) {
+    let width = |s: &str| s.chars().count();
+    let label_width = block.iter()
+        .filter_map(|rule| rule.label.as_deref().map(width))
+        .max();
+    let left_width = block.iter().map(|rule| width(&rule.left)).max().unwrap_or(0);
+    for rule in block {
+        let mut line = String::new();
+        if let Some(label_width) = label_width {
+            let label = rule.label.as_deref().unwrap_or("");
+            line.push_str(&format!("{:<w$} ", label, w = label_width));
+        }
+        line.push_str(&format!("{:<w$} -> {}", rule.left, rule.right, w = left_width));
+        if let Some(comment) = &rule.comment {
+            line.push_str(&format!(" {}", comment));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+}
+
+fn write_steps(out: &mut String, steps: &[Step]) {
+    let term_width = steps.iter().map(|step| step.term.chars().count()).max().unwrap_or(0);
+    for step in steps {
+        out.push_str(&format!("= {:<w$} {{by {}}}", step.term, step.label, w = term_width));
+        if let Some(comment) = &step.comment {
+            out.push_str(&format!(" {}", comment));
+        }
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::trs::Trs;
+
+    #[test]
+    fn test_align_block() {
+        let src = "// addition\n[add-0]   $x +  0 ->   $x\n$x  + (S $y) -> S ( $x + $y )  //  step\n\nnot true -> false\n";
+        assert_eq!(
+            format_source(src).unwrap(),
+            "// addition\n[add-0] $x + 0      -> $x\n        $x + (S $y) -> S ($x + $y) // step\n\nnot true -> false\n"
+        );
+    }
+
+    #[test]
+    fn test_align_steps() {
+        let src = "proof (S 0) + (S 0)\n= S ((S 0) + 0) {by define-addition-1}\n=   S (S 0)   {by define-addition-0} // done\nqed\n";
+        assert_eq!(
+            format_source(src).unwrap(),
+            "proof (S 0) + (S 0)\n= S ((S 0) + 0) {by define-addition-1}\n= S (S 0)       {by define-addition-0} // done\nqed\n"
+        );
+    }
+
+    #[test]
+    fn test_preserves_normal_forms() {
+        let src = "f (a) -> matched\ng ((b c)) $x -> ($x)\nh (S $x) ((0)) -> $x";
+        let formatted = format_source(src).unwrap();
+        let normal_form = |src: &str, term: &str| {
+            let mut trs = Trs::from_source(src).unwrap();
+            let derivation = trs.derive(term, 10).unwrap();
+            derivation.result().bind(trs.symbols()).to_string()
+        };
+        for term in ["f a", "f ((a))", "g (b c) d", "g b c d", "h (S 1) 0"] {
+            assert_eq!(normal_form(src, term), normal_form(&formatted, term), "{}", term);
+        }
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let once = format_source(include_str!("../peano.txt")).unwrap();
+        assert_eq!(format_source(&once).unwrap(), once);
+    }
+
+}
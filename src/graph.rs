@@ -0,0 +1,414 @@
+//! Term-graph rewriting.
+//!
+//! A rule that mentions a variable more than once on its right side,
+//! like `$x * (S $y) -> $x + ($x * $y)`, copies the bound term each time
+//! it is applied. In a term graph the copies are references to the same
+//! node, and rewriting that node rewrites it for every reference.
+//! Shared subterms are printed once, as `#1=(S 0)`, and then as `#1#`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::expr::*;
+use crate::matches::VariableBinding;
+use crate::profile::{Profile, Timer};
+use crate::reduce::{RewriteRule, RewriteRules, Strategy};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+pub type NodeId = usize;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Symbol(SymbolHandle),
+    /// Several terms, parenthesized unless it is the whole term
+    Compound(Vec<NodeId>),
+    /// Any other terminal, which is never rewritten
+    Leaf(Terminal),
+    /// A node that was rewritten to an existing node
+    Ref(NodeId)
+}
+
+type Bindings = Vec<(SymbolHandle, VariableKind, NodeId)>;
+
+/// A term whose subterms may be shared.
+#[derive(Debug, Clone)]
+pub struct TermGraph {
+    nodes: Vec<Node>,
+    root: NodeId
+}
+
+impl TermGraph {
+
+    pub fn new(expr: &Expression) -> Self {
+        let mut graph = TermGraph { nodes: vec![], root: 0 };
+        graph.root = graph.add_expression(expr);
+        graph
+    }
+
+    /// Binders are only supported by tree rewriting, which shifts
+    /// and renames their variables as it copies terms.
+    pub fn supports(expr: &Expression, rules: &RewriteRules) -> bool {
+        fn plain(expr: &Expression) -> bool {
+            expr.0.iter().all(|t| match t {
+                Terminal::Parentheses(e) => plain(e),
+                Terminal::Binder(..) | Terminal::Bound(_) | Terminal::Substitution(..) => false,
+                _ => true
+            })
+        }
+        plain(expr) && rules.iter().all(|rule| plain(&rule.left) && plain(&rule.right))
+    }
+
+    /// The term this graph stands for, with every shared subterm copied.
+    pub fn expression(&self) -> Expression {
+        match &self.nodes[self.resolve(self.root)] {
+            Node::Compound(children) => Expression(children.iter().map(|c| self.terminal(*c)).collect()),
+            _ => Expression(vec![self.terminal(self.root)])
+        }
+    }
+
+    /// How many distinct nodes make up the term, counting shared ones once.
+    pub fn size(&self) -> usize {
+        fn visit(graph: &TermGraph, id: NodeId, seen: &mut HashSet<NodeId>) {
+            let id = graph.resolve(id);
+            if seen.insert(id) {
+                if let Node::Compound(children) = &graph.nodes[id] {
+                    for child in children {
+                        visit(graph, *child, seen);
+                    }
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        visit(self, self.root, &mut seen);
+        // Like `Expression::size`, compound nodes themselves are not counted
+        seen.iter().filter(|id| !matches!(self.nodes[**id], Node::Compound(_))).count()
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn add_expression(&mut self, expr: &Expression) -> NodeId {
+        match expr.0.as_slice() {
+            [term] => self.add_terminal(term),
+            terms => {
+                let children = terms.iter().map(|t| self.add_terminal(t)).collect();
+                self.push(Node::Compound(children))
+            }
+        }
+    }
+
+    fn add_terminal(&mut self, term: &Terminal) -> NodeId {
+        match term {
+            Terminal::Symbol(s) => self.push(Node::Symbol(*s)),
+            Terminal::Parentheses(e) => self.add_expression(e),
+            _ => self.push(Node::Leaf(term.clone()))
+        }
+    }
+
+    fn resolve(&self, mut id: NodeId) -> NodeId {
+        while let Node::Ref(target) = self.nodes[id] {
+            id = target;
+        }
+        id
+    }
+
+    fn terminal(&self, id: NodeId) -> Terminal {
+        match &self.nodes[self.resolve(id)] {
+            Node::Symbol(s) => Terminal::Symbol(*s),
+            Node::Compound(children) => Terminal::Parentheses(Expression(children.iter().map(|c| self.terminal(*c)).collect())),
+            Node::Leaf(term) => term.clone(),
+            Node::Ref(_) => unreachable!("references are resolved")
+        }
+    }
+
+    /// True if both nodes stand for the same term.
+    fn equal(&self, a: NodeId, b: NodeId) -> bool {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        if a == b {
+            return true;
+        }
+        match (&self.nodes[a], &self.nodes[b]) {
+            (Node::Symbol(x), Node::Symbol(y)) => x == y,
+            (Node::Compound(xs), Node::Compound(ys)) => xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.equal(*x, *y)),
+            (Node::Leaf(x), Node::Leaf(y)) => x == y,
+            _ => false
+        }
+    }
+
+    /// Like `Expression::matches`, but binding variables to nodes.
+    fn match_expression(&self, pattern: &Expression, id: NodeId, bindings: &mut Bindings, symbols: &SymbolTable) -> bool {
+        let id = self.resolve(id);
+        match (pattern.0.as_slice(), &self.nodes[id]) {
+            (terms, Node::Compound(children)) if terms.len() != 1 => {
+                terms.len() == children.len() && terms.iter().zip(children).all(|(t, c)| self.match_terminal(t, *c, bindings, symbols))
+            },
+            ([term], node) if !matches!(node, Node::Compound(_)) => self.match_terminal(term, id, bindings, symbols),
+            _ => false
+        }
+    }
+
+    fn match_terminal(&self, pattern: &Terminal, id: NodeId, bindings: &mut Bindings, symbols: &SymbolTable) -> bool {
+        let id = self.resolve(id);
+        match (pattern, &self.nodes[id]) {
+            (Terminal::Symbol(a), Node::Symbol(b)) => a == b,
+            (Terminal::Parentheses(p), Node::Compound(children)) => {
+                p.0.len() == children.len() && p.0.iter().zip(children).all(|(t, c)| self.match_terminal(t, *c, bindings, symbols))
+            },
+            (Terminal::Variable(v, kind, guard), node) => {
+                let accepted = match guard.as_deref() {
+                    None => true,
+                    Some(Guard::Symbol) => matches!(node, Node::Symbol(_)),
+                    Some(Guard::Compound) => matches!(node, Node::Compound(_)),
+                    Some(Guard::Name(pattern)) => matches!(node, Node::Symbol(s) if symbols.accepts(pattern, *s)),
+                    Some(Guard::OneOf(members)) => matches!(node, Node::Symbol(s) if members.contains(s))
+                };
+                if !accepted {
+                    return false;
+                }
+                if let Some(&(_, _, bound)) = bindings.iter().find(|b| b.0 == *v) {
+                    return self.equal(bound, id);
+                }
+                bindings.push((*v, *kind, id));
+                true
+            },
+            (pattern, Node::Leaf(term)) => pattern == term,
+            _ => false
+        }
+    }
+
+    /// The bindings of `rule` if its left side matches the node `id`
+    /// and its distinct variables and constraints hold.
+    fn bindings(&self, rule: &RewriteRule, id: NodeId, symbols: &SymbolTable) -> Option<Bindings> {
+        let mut bindings = vec![];
+        if !self.match_expression(&rule.left, id, &mut bindings, symbols) {
+            return None;
+        }
+        let distinct = bindings.iter()
+            .filter(|d| d.1 == VariableKind::Distinct)
+            .all(|d| bindings.iter().all(|b| b.0 == d.0 || !self.equal(b.2, d.2)));
+        if !distinct {
+            return None;
+        }
+        if !rule.constraints.is_empty() {
+            let terms: Vec<Terminal> = bindings.iter().map(|b| self.terminal(b.2)).collect();
+            let tree: Vec<VariableBinding> = bindings.iter().zip(&terms)
+                .map(|(b, expr)| VariableBinding { var: b.0, expr, kind: b.1, depth: 0 })
+                .collect();
+            if !rule.constraints.iter().all(|c| c.holds(&tree, symbols)) {
+                return None;
+            }
+        }
+        Some(bindings)
+    }
+
+    /// Build the right side of a rule, referring to the bound nodes.
+    fn instantiate(&mut self, expr: &Expression, bindings: &Bindings) -> NodeId {
+        match expr.0.as_slice() {
+            [term] => self.instantiate_terminal(term, bindings),
+            terms => {
+                let children = terms.iter().map(|t| self.instantiate_terminal(t, bindings)).collect();
+                self.push(Node::Compound(children))
+            }
+        }
+    }
+
+    fn instantiate_terminal(&mut self, term: &Terminal, bindings: &Bindings) -> NodeId {
+        match term {
+            Terminal::Variable(v, ..) => match bindings.iter().find(|b| b.0 == *v) {
+                Some(b) => b.2,
+                None => self.push(Node::Leaf(term.clone()))
+            },
+            Terminal::Parentheses(e) => self.instantiate(e, bindings),
+            _ => self.add_terminal(term)
+        }
+    }
+
+    /// Apply a single reduction step, choosing the redex like
+    /// `Expression::rewrite_once_with`. Returns false in normal form.
+    /// Match attempts and firings are counted in `profile`, if given.
+    pub fn rewrite_once(&mut self, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, mut profile: Option<&mut Profile>) -> bool {
+        self.rewrite_node(self.root, rules, symbols, strategy, &mut profile)
+    }
+
+    fn rewrite_node(&mut self, id: NodeId, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, profile: &mut Option<&mut Profile>) -> bool {
+        let id = self.resolve(id);
+        match strategy {
+            Strategy::Innermost => self.rewrite_children(id, rules, symbols, strategy, profile) || self.rewrite_root(id, rules, symbols, profile),
+            Strategy::Outermost => self.rewrite_root(id, rules, symbols, profile) || self.rewrite_children(id, rules, symbols, strategy, profile)
+        }
+    }
+
+    fn rewrite_children(&mut self, id: NodeId, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, profile: &mut Option<&mut Profile>) -> bool {
+        let Node::Compound(children) = &self.nodes[id] else {
+            return false;
+        };
+        let children = children.clone();
+        // Only the symbols matter to `:lazy` declarations
+        let shape = Expression(children.iter().map(|c| match self.nodes[self.resolve(*c)] {
+            Node::Symbol(s) => Terminal::Symbol(s),
+            _ => Terminal::Parentheses(Expression(vec![]))
+        }).collect());
+        children.iter().enumerate()
+            .any(|(idx, child)| !rules.is_frozen(&shape, idx) && self.rewrite_node(*child, rules, symbols, strategy, profile))
+    }
+
+    /// Rewrite the node `id` in place, so every reference sees the result.
+    fn rewrite_root(&mut self, id: NodeId, rules: &RewriteRules, symbols: &SymbolTable, profile: &mut Option<&mut Profile>) -> bool {
+        let profiling = profile.is_some();
+        let found = rules.iter().enumerate().find_map(|(idx, rule)| {
+            let timer = Timer::start_if(profiling);
+            let bindings = self.bindings(rule, id, symbols);
+            if let Some(profile) = profile.as_deref_mut() {
+                profile.attempt(idx, bindings.is_some(), timer.elapsed());
+            }
+            Some((idx, rule, bindings?))
+        });
+        let Some((idx, rule, mut bindings)) = found else {
+            return false;
+        };
+        // A variable bound to the redex itself keeps referring to its old term
+        if bindings.iter().any(|b| b.2 == id) {
+            let old = self.push(self.nodes[id].clone());
+            for b in bindings.iter_mut().filter(|b| b.2 == id) {
+                b.2 = old;
+            }
+        }
+        let timer = Timer::start_if(profiling);
+        let before = self.nodes.len();
+        let result = self.instantiate(&rule.right, &bindings);
+        if let Some(profile) = profile.as_deref_mut() {
+            profile.fire(idx, timer.elapsed());
+        }
+        self.nodes[id] = if result >= before {
+            // The new node was pushed last, move it into place
+            self.nodes.pop().unwrap()
+        } else {
+            Node::Ref(result)
+        };
+        true
+    }
+
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundTermGraph<'s> {
+        BoundTermGraph { symbols, graph: self }
+    }
+
+}
+
+pub struct BoundTermGraph<'s> {
+    symbols: &'s SymbolTable,
+    graph: &'s TermGraph
+}
+
+impl<'s> BoundTermGraph<'s> {
+
+    /// How many compound nodes refer to each node reachable from the root.
+    fn references(&self, id: NodeId, seen: &mut HashMap<NodeId, usize>) {
+        let id = self.graph.resolve(id);
+        if let Node::Compound(children) = &self.graph.nodes[id] {
+            for child in children {
+                let child = self.graph.resolve(*child);
+                let count = seen.entry(child).or_insert(0);
+                *count += 1;
+                if *count == 1 {
+                    self.references(child, seen);
+                }
+            }
+        }
+    }
+
+    fn write_node(&self, f: &mut std::fmt::Formatter<'_>, id: NodeId, shared: &HashMap<NodeId, usize>, labels: &mut HashMap<NodeId, usize>) -> std::fmt::Result {
+        let id = self.graph.resolve(id);
+        match &self.graph.nodes[id] {
+            Node::Symbol(s) => write!(f, "{}", self.symbols.lookup(*s)),
+            Node::Leaf(term) => write!(f, "{}", Expression(vec![term.clone()]).bind(self.symbols)),
+            Node::Compound(_) if labels.contains_key(&id) => write!(f, "#{}#", labels[&id]),
+            Node::Compound(children) => {
+                if shared.get(&id).is_some_and(|count| *count > 1) {
+                    let label = labels.len() + 1;
+                    labels.insert(id, label);
+                    write!(f, "#{}=", label)?;
+                }
+                write!(f, "(")?;
+                self.write_children(f, children, shared, labels)?;
+                write!(f, ")")
+            },
+            Node::Ref(_) => unreachable!("references are resolved")
+        }
+    }
+
+    fn write_children(&self, f: &mut std::fmt::Formatter<'_>, children: &[NodeId], shared: &HashMap<NodeId, usize>, labels: &mut HashMap<NodeId, usize>) -> std::fmt::Result {
+        for (idx, child) in children.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            self.write_node(f, *child, shared, labels)?;
+        }
+        Ok(())
+    }
+
+}
+
+impl<'s> Display for BoundTermGraph<'s> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut shared = HashMap::new();
+        self.references(self.graph.root, &mut shared);
+        let mut labels = HashMap::new();
+        // The whole term is not parenthesized, like an expression
+        match &self.graph.nodes[self.graph.resolve(self.graph.root)] {
+            Node::Compound(children) => self.write_children(f, children, &shared, &mut labels),
+            _ => self.write_node(f, self.graph.root, &shared, &mut labels)
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::parse::TryParse;
+
+    fn rules(src: &str, symbols: &mut SymbolTable) -> RewriteRules {
+        let mut rules = RewriteRules::new();
+        for line in src.lines() {
+            if let Statement::Rewrite(l, r, c) = Statement::parse(line, symbols).unwrap() {
+                rules.add(RewriteRule::new(l, r).with_constraints(c));
+            }
+        }
+        rules
+    }
+
+    #[test]
+    fn test_shared_rewrite() {
+        let mut symbols = SymbolTable::new();
+        let rules = rules("double $x -> pair $x $x\ninc $x -> S $x", &mut symbols);
+        let mut graph = TermGraph::new(&Expression::parse("double (inc 0)", &mut symbols).unwrap());
+        assert!(graph.rewrite_once(&rules, &symbols, Strategy::Outermost, None));
+        assert_eq!(graph.bind(&symbols).to_string(), "pair #1=(inc 0) #1#");
+        // Both references see the rewritten node
+        assert!(graph.rewrite_once(&rules, &symbols, Strategy::Outermost, None));
+        assert_eq!(graph.bind(&symbols).to_string(), "pair #1=(S 0) #1#");
+        assert!(!graph.rewrite_once(&rules, &symbols, Strategy::Outermost, None));
+        assert_eq!(graph.expression(), Expression::parse("pair (S 0) (S 0)", &mut symbols).unwrap());
+    }
+
+    #[test]
+    fn test_same_normal_form() {
+        let mut symbols = SymbolTable::new();
+        let src = "$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)\n$x * 0 -> 0\n$x * (S $y) -> $x + ($x * $y)";
+        let rules = rules(src, &mut symbols);
+        let term = Expression::parse("(S (S 0)) * (S (S (S 0)))", &mut symbols).unwrap();
+        let mut graph = TermGraph::new(&term);
+        let mut steps = 0;
+        while graph.rewrite_once(&rules, &symbols, Strategy::Innermost, None) {
+            steps += 1;
+        }
+        let tree = term.derive(&rules, &symbols, 100);
+        assert_eq!(graph.expression(), *tree.result());
+        assert!(steps <= tree.steps.len());
+    }
+
+}
@@ -1,3 +1,4 @@
+use crate::binder::open;
 use crate::matches::VariableBinding;
 use crate::expr::*;
 
@@ -8,16 +9,44 @@ pub trait Interpolate {
 impl Interpolate for Terminal {
 
     fn interpolate<'t>(&self, bindings: &[VariableBinding<'t>]) -> Self {
+        self.interpolate_at(bindings, 0)
+    }
+
+}
+
+impl Terminal {
+
+    /// Interpolate under `depth` binders of the right side of a rule,
+    /// shifting bound terms that refer to binders outside the match.
+    fn interpolate_at(&self, bindings: &[VariableBinding<'_>], depth: usize) -> Self {
         match self {
-            Terminal::Parentheses(e) => Terminal::Parentheses(e.interpolate(bindings)),
+            // A variable alone in parentheses may stand for a single term
+            Terminal::Parentheses(e) => {
+                let mut inner = e.interpolate_at(bindings, depth);
+                if inner.0.len() == 1 {
+                    inner.0.pop().unwrap()
+                } else {
+                    Terminal::Parentheses(inner)
+                }
+            },
             Terminal::Symbol(s) => Terminal::Symbol(*s),
-            Terminal::Variable(v, k) => {
+            Terminal::Variable(v, k, guard) => {
                 for b in bindings {
                     if b.var == *v {
-                        return b.expr.clone()
+                        return b.at_depth(depth)
                     }
                 }
-                Terminal::Variable(*v, *k)
+                Terminal::Variable(*v, *k, guard.clone())
+            },
+            Terminal::Binder(hint, body) => Terminal::Binder(*hint, Box::new(body.interpolate_at(bindings, depth + 1))),
+            Terminal::Bound(idx) => Terminal::Bound(*idx),
+            Terminal::Substitution(v, arg) => {
+                let arg = arg.interpolate_at(bindings, depth);
+                match bindings.iter().find(|b| b.var == *v) {
+                    // The body is seen from inside the binder that is being removed
+                    Some(b) => open(&b.at_depth(depth + 1), &arg),
+                    None => Terminal::Substitution(*v, Box::new(arg))
+                }
             }
         }
     }
@@ -27,14 +56,22 @@ impl Interpolate for Terminal {
 impl Interpolate for Expression {
 
     fn interpolate<'t>(&self, bindings: &[VariableBinding<'t>]) -> Self {
+        self.interpolate_at(bindings, 0)
+    }
+
+}
+
+impl Expression {
+
+    fn interpolate_at(&self, bindings: &[VariableBinding<'_>], depth: usize) -> Self {
         let mut interpolated = vec![];
         for term in &self.0 {
-            interpolated.push(term.interpolate(bindings))
+            interpolated.push(term.interpolate_at(bindings, depth))
         }
-        // We may need to remove unnecessary parens after a transplant...
-        if interpolated.len() == 1 {
-            if let Terminal::Parentheses(e) = &interpolated[0] {
-                return e.clone()
+        // A lone variable bound to a compound term leaves its parentheses behind
+        if let [Terminal::Parentheses(_)] = interpolated.as_slice() {
+            if let Some(Terminal::Parentheses(e)) = interpolated.pop() {
+                return e;
             }
         }
         Expression(interpolated)
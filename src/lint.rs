@@ -0,0 +1,256 @@
+//! Warnings about rules that can never fire, or fire on every term.
+//!
+//! Rules are tried in order and the first one that matches is applied,
+//! so a rule is dead when an earlier one matches every term it does.
+//! In a file that is a whole program rather than a library, a rule is
+//! also dead when its left side needs a symbol that neither another
+//! rule nor any query or assertion in the file produces.
+
+use crate::expr::*;
+use crate::parse::{ErrorKind, ParseError, TryParse};
+use crate::reduce::{RewriteRule, RewriteRules};
+use crate::strategy::selects;
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+use crate::trs::SourceError;
+use crate::validate::{matches_everything, validate, InvalidRule};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// The rule on this line matches every term this one does. `define`
+    /// gives the index of the earlier rule instead of its line.
+    Subsumed(usize),
+    /// Nothing produces this symbol of the left side
+    Unreachable(SymbolHandle),
+    /// The left side is a lone variable and no strategy selects the rule
+    MatchesEverything
+}
+
+/// A rule that can never fire, lines start from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub lint: Lint
+}
+
+impl Lint {
+
+    /// `earlier` names the rule a `Subsumed` lint refers to.
+    pub fn describe(&self, symbols: &SymbolTable, earlier: &str) -> String {
+        match self {
+            Lint::Subsumed(_) => format!("rule never fires, {} matches every term it does", earlier),
+            Lint::Unreachable(symbol) => format!("rule never fires, nothing produces \"{}\"", symbols.lookup(*symbol)),
+            Lint::MatchesEverything => "rule matches every term, it is only useful when a strategy applies it by its label".to_owned()
+        }
+    }
+
+}
+
+impl Warning {
+
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let earlier = match self.lint {
+            Lint::Subsumed(line) => format!("the rule on line {}", line + 1),
+            _ => String::new()
+        };
+        self.lint.describe(symbols, &earlier)
+    }
+
+}
+
+/// The index of the first rule in `rules` that matches every term `rule` does.
+/// Rules with constraints or distinct variables are skipped, whether
+/// they match depends on more than the shape of the term. So are rules
+/// that a strategy calling one of `calls` passes over when it selects `rule`.
+pub fn subsumed_by(rules: &RewriteRules, rule: &RewriteRule, symbols: &SymbolTable, calls: &[SymbolHandle]) -> Option<usize> {
+    rules.iter().position(|earlier| {
+        earlier.constraints.is_empty()
+            && !has_distinct(&earlier.left.0)
+            && calls.iter().all(|name| !selects(*name, rule.label, symbols) || selects(*name, earlier.label, symbols))
+            && earlier.bindings(&rule.left, symbols).is_some()
+    })
+}
+
+/// Add `rule` to `rules`, the rules defined before it, and return the lints
+/// that do not depend on the rest of the file. Invalid rules are not added.
+/// `calls` are the names of the strategies that may apply rules by their label.
+pub fn define(rules: &mut RewriteRules, rule: RewriteRule, symbols: &SymbolTable, calls: &[SymbolHandle]) -> Result<Vec<Lint>, InvalidRule> {
+    validate(&rule)?;
+    let mut lints = vec![];
+    if matches_everything(&rule) && !calls.iter().any(|name| selects(*name, rule.label, symbols)) {
+        lints.push(Lint::MatchesEverything);
+    }
+    if let Some(idx) = subsumed_by(rules, &rule, symbols, calls) {
+        lints.push(Lint::Subsumed(idx));
+    }
+    rules.add(rule);
+    Ok(lints)
+}
+
+fn has_distinct(terms: &[Terminal]) -> bool {
+    terms.iter().any(|term| match term {
+        Terminal::Variable(_, kind, _) => *kind == VariableKind::Distinct,
+        Terminal::Parentheses(e) => has_distinct(&e.0),
+        Terminal::Binder(_, body) => has_distinct(std::slice::from_ref(body.as_ref())),
+        _ => false
+    })
+}
+
+/// Every symbol in `terms`, including those under parentheses and binders.
+fn collect_symbols(terms: &[Terminal], out: &mut Vec<SymbolHandle>) {
+    for term in terms {
+        match term {
+            Terminal::Symbol(s) if !out.contains(s) => out.push(*s),
+            Terminal::Parentheses(e) => collect_symbols(&e.0, out),
+            Terminal::Binder(_, body) | Terminal::Substitution(_, body) => collect_symbols(std::slice::from_ref(body.as_ref()), out),
+            _ => {}
+        }
+    }
+}
+
+/// The terms a statement starts rewriting from.
+fn start_terms(statement: &Statement, symbols: &SymbolTable) -> Vec<Expression> {
+    match statement {
+        Statement::Rewrite(l, r, _) if r.is_query(symbols) => vec![l.clone()],
+        Statement::Reaches(l, _) | Statement::NotReaches(l, _) | Statement::Expect(l, _) => vec![l.clone()],
+        Statement::Proof(e) | Statement::Step(e, _) | Statement::Apply(_, e) => vec![e.clone()],
+        Statement::Property(_, body) => start_terms(body, symbols),
+        _ => vec![]
+    }
+}
+
+/// Find the rules in `src` that can never fire or match every term. Lines
+/// that fail to parse or define an invalid rule are reported as errors,
+/// like `Trs::from_source` does. Only with `unused`
+/// are rules reported whose left side nothing in `src` produces, as the
+/// rules of a library are applied to terms written elsewhere.
+pub fn lint_source(src: &str, unused: bool) -> Result<(SymbolTable, Vec<Warning>), Vec<SourceError>> {
+    let mut symbols = SymbolTable::new();
+    // The rules with their lines, the symbols queries start from and the names strategies call
+    let mut defined = vec![];
+    let mut queried = vec![];
+    let mut calls = vec![];
+    let mut errors = vec![];
+    for (line, text) in src.lines().enumerate() {
+        match Item::parse_complete(text, &mut symbols) {
+            Ok(item) => match item.statement {
+                Statement::Rewrite(l, r, constraints) if !r.is_query(&symbols) => {
+                    defined.push((line, RewriteRule::labeled(item.label.map(|label| label.0), l, r).with_constraints(constraints)));
+                },
+                statement => {
+                    if let Statement::Strategy(_, strategy) | Statement::Apply(strategy, _) = &statement {
+                        strategy.calls(&mut calls);
+                    }
+                    for term in start_terms(&statement, &symbols) {
+                        collect_symbols(&term.0, &mut queried);
+                    }
+                }
+            },
+            Err(error) => errors.push(SourceError { line, error })
+        }
+    }
+
+    // Strategies may select rules defined above them, so rules are checked once all calls are known
+    let mut rules = RewriteRules::new();
+    let mut lines = vec![];
+    let mut warnings = vec![];
+    for (line, rule) in defined {
+        match define(&mut rules, rule, &symbols, &calls) {
+            Ok(lints) => {
+                warnings.extend(lints.into_iter().map(|lint| match lint {
+                    Lint::Subsumed(idx) => Warning { line, lint: Lint::Subsumed(lines[idx]) },
+                    lint => Warning { line, lint }
+                }));
+                lines.push(line);
+            },
+            Err(invalid) => {
+                let error = ParseError::new(0, ErrorKind::InvalidRule(invalid.describe(&symbols)));
+                errors.push(SourceError { line, error });
+            }
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.line);
+        return Err(errors);
+    }
+
+    for (idx, rule) in rules.iter().enumerate() {
+        let subsumed = warnings.iter().any(|w| w.line == lines[idx] && matches!(w.lint, Lint::Subsumed(_)));
+        if unused && !subsumed {
+            let mut produced = queried.clone();
+            for (other, r) in rules.iter().enumerate() {
+                if other != idx {
+                    collect_symbols(&r.right.0, &mut produced);
+                }
+            }
+            let mut needed = vec![];
+            collect_symbols(&rule.left.0, &mut needed);
+            if let Some(missing) = needed.into_iter().find(|s| !produced.contains(s)) {
+                warnings.push(Warning { line: lines[idx], lint: Lint::Unreachable(missing) });
+            }
+        }
+    }
+    warnings.sort_by_key(|warning| warning.line);
+    Ok((symbols, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::trs::Trs;
+
+    fn lint(src: &str, unused: bool) -> Vec<String> {
+        let (symbols, warnings) = lint_source(src, unused).unwrap();
+        warnings.iter().map(|w| format!("{}: {}", w.line, w.describe(&symbols))).collect()
+    }
+
+    #[test]
+    fn test_subsumed() {
+        let src = "$x + 0 -> $x\nnot $x -> no\nnot true -> false\n1 + 0 -> 1\n1 + 0 -> ?\nnot true -> ?";
+        assert_eq!(lint(src, false), vec![
+            "2: rule never fires, the rule on line 2 matches every term it does",
+            "3: rule never fires, the rule on line 1 matches every term it does"
+        ]);
+    }
+
+    #[test]
+    fn test_selected_by_strategy() {
+        let src = "[any] not $x -> no\n[true] not true -> false\napply true: not true";
+        assert!(lint(src, false).is_empty());
+        // Selecting both rules still picks the first
+        let src = "[not-any] not $x -> no\n[not-true] not true -> false\napply not-*: not true";
+        assert_eq!(lint(src, false), vec!["1: rule never fires, the rule on line 1 matches every term it does"]);
+    }
+
+    #[test]
+    fn test_matches_everything() {
+        let src = "[twice] $x -> $x $x\n[wrap] $x -> w $x\napply wrap: a";
+        assert_eq!(lint(src, false), vec!["0: rule matches every term, it is only useful when a strategy applies it by its label"]);
+        assert!(lint_source("f $x -> $y", false).is_err());
+    }
+
+    #[test]
+    fn test_define() {
+        let mut trs = Trs::new();
+        let items: Vec<Item> = ["not $x -> no", "[any] $x -> a", "not true -> false", "f $x -> $y"].iter()
+            .map(|src| trs.parse_item(src).unwrap())
+            .collect();
+        assert_eq!(trs.define(&items[0]).unwrap(), vec![]);
+        assert_eq!(trs.define(&items[1]).unwrap(), vec![Lint::MatchesEverything]);
+        // Subsumed by the rule at index 0, not the line
+        assert_eq!(trs.define(&items[2]).unwrap(), vec![Lint::Subsumed(0)]);
+        assert!(trs.define(&items[3]).is_err());
+        assert_eq!(trs.rules().len(), 3);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let src = "loop -> loop\nsucc $x -> S $x\nif true then $x else $y -> $x\n1 + 1 -> ?\nassert if true then 1 else 0 ->* 1";
+        assert_eq!(lint(src, true), vec![
+            "0: rule never fires, nothing produces \"loop\"",
+            "1: rule never fires, nothing produces \"succ\""
+        ]);
+        assert!(lint(src, false).is_empty());
+    }
+
+}
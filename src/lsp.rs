@@ -0,0 +1,379 @@
+//! A language server for rule files, speaking JSON-RPC over stdio.
+//!
+//! Every change re-parses the whole document, rule files are small.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{CodeActionRequest, Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::*;
+
+use crate::expr::*;
+use crate::reduce::RewriteRule;
+use crate::symbol_table::SymbolHandle;
+use crate::trs::{SourceError, Trs};
+
+/// The inline evaluation gives up after this many steps
+const MAX_EVAL_STEPS: usize = 10_000;
+
+/// A parsed rule file.
+pub struct Document {
+    text: String,
+    // The rules and symbols, defined the same way `Trs::load` does
+    trs: Trs,
+    // The parsed item of each line, if it parsed
+    items: Vec<Option<Item>>,
+    errors: Vec<SourceError>,
+    // The line each rule was defined on
+    rule_lines: Vec<usize>
+}
+
+impl Document {
+
+    pub fn parse(text: &str) -> Self {
+        let mut trs = Trs::new();
+        let mut items = vec![];
+        let mut errors = vec![];
+        let mut rule_lines = vec![];
+        for (line, src) in text.lines().enumerate() {
+            match trs.parse_item(src) {
+                Ok(item) => {
+                    let before = trs.rules().len();
+                    if let Err(error) = trs.define(&item) {
+                        errors.push(SourceError { line, error });
+                    }
+                    if trs.rules().len() > before {
+                        rule_lines.push(line);
+                    }
+                    items.push(Some(item));
+                },
+                Err(error) => {
+                    errors.push(SourceError { line, error });
+                    items.push(None);
+                }
+            }
+        }
+        Document { text: text.to_owned(), trs, items, errors, rule_lines }
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.text.lines().nth(line).unwrap_or("")
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter()
+            .map(|error| {
+                let text = self.line(error.line);
+                let start = utf16_column(text, error.column());
+                let end = utf16_column(text, text.len()).max(start + 1);
+                Diagnostic {
+                    range: Range::new(Position::new(error.line as u32, start), Position::new(error.line as u32, end)),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("peano".to_owned()),
+                    message: error.error.kind().to_string(),
+                    ..Diagnostic::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The symbol, variable or label under `position`.
+    fn word_at(&self, position: Position) -> Option<&str> {
+        let text = self.line(position.line as usize);
+        let idx = byte_index(text, position.character);
+        let is_boundary = |c: char| c.is_whitespace() || "()[]{}".contains(c);
+        let start = text[..idx].rfind(is_boundary).map(|i| i + 1).unwrap_or(0);
+        let end = text[idx..].find(is_boundary).map(|i| idx + i).unwrap_or(text.len());
+        let word = &text[start..end];
+        if word.is_empty() { None } else { Some(word) }
+    }
+
+    fn format_rule(&self, rule: &RewriteRule) -> String {
+        let symbols = self.trs.symbols();
+        let rule_text = format!("{} -> {}", rule.left.bind(symbols), rule.right.bind(symbols));
+        match rule.label {
+            Some(label) => format!("[{}] {}", symbols.lookup(label), rule_text),
+            None => rule_text
+        }
+    }
+
+    /// The rules whose left-hand side starts with the symbol under `position`,
+    /// or the rule with that label, as Markdown. Leading variables are skipped,
+    /// so hovering `+` shows `$x + 0 -> $x`.
+    pub fn hover(&self, position: Position) -> Option<String> {
+        let word = self.word_at(position)?;
+        let handle = self.trs.symbols().find(word)?;
+        let rules: Vec<String> = self.trs.rules().iter()
+            .filter(|rule| rule.label == Some(handle) || head(&rule.left) == Some(handle))
+            .map(|rule| self.format_rule(rule))
+            .collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(format!("```\n{}\n```", rules.join("\n")))
+    }
+
+    /// Where the rule labeled with the word under `position` is defined.
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let handle = self.trs.symbols().find(self.word_at(position)?)?;
+        let idx = self.trs.rules().iter().position(|rule| rule.label == Some(handle))?;
+        let line = self.rule_lines[idx] as u32;
+        let text = self.line(line as usize);
+        Some(Range::new(Position::new(line, 0), Position::new(line, utf16_column(text, text.len()))))
+    }
+
+    pub fn completions(&self) -> Vec<CompletionItem> {
+        let labels: Vec<&str> = self.trs.rules().iter()
+            .filter_map(|rule| rule.label.map(|label| self.trs.symbols().lookup(label)))
+            .collect();
+        self.trs.symbols().iter()
+            .map(|symbol| CompletionItem {
+                label: symbol.to_owned(),
+                kind: Some(if labels.contains(&symbol) { CompletionItemKind::REFERENCE } else { CompletionItemKind::CONSTANT }),
+                ..CompletionItem::default()
+            })
+            .collect()
+    }
+
+    /// Normalize the query on `line` with the rules defined above it,
+    /// returning an edit that writes the result as a trailing comment.
+    pub fn evaluate(&self, line: usize) -> Option<TextEdit> {
+        let item = self.items.get(line)?.as_ref()?;
+        let term = match &item.statement {
+            Statement::Rewrite(l, r, _) if r.is_query(self.trs.symbols()) => l,
+            _ => return None
+        };
+        // Only what is defined above the query, as when running the file
+        let mut above = self.trs.clone();
+        above.clear();
+        for item in self.items[..line].iter().flatten() {
+            // Invalid rules were reported as diagnostics already
+            let _ = above.define(item);
+        }
+        let derivation = term.derive(above.rules(), above.symbols(), MAX_EVAL_STEPS);
+        let result = if derivation.truncated {
+            format!("no normal form after {} steps", MAX_EVAL_STEPS)
+        } else {
+            format!("= {}", derivation.result().bind(self.trs.symbols()))
+        };
+
+        let text = self.line(line);
+        let code = match item.comment {
+            Some(_) => text.find("//").map(|idx| &text[..idx]).unwrap_or(text),
+            None => text
+        };
+        Some(TextEdit {
+            range: Range::new(Position::new(line as u32, 0), Position::new(line as u32, utf16_column(text, text.len()))),
+            new_text: format!("{} // {}", code.trim_end(), result)
+        })
+    }
+
+}
+
+/// The first symbol of `expr`, not counting variables.
+fn head(expr: &Expression) -> Option<SymbolHandle> {
+    expr.0.iter()
+        .find(|term| !matches!(term, Terminal::Variable(..)))
+        .and_then(|term| match term {
+            Terminal::Symbol(s) => Some(*s),
+            _ => None
+        })
+}
+
+/// Convert a byte index within `line` to the UTF-16 column LSP clients expect.
+fn utf16_column(line: &str, idx: usize) -> u32 {
+    line[..idx.min(line.len())].encode_utf16().count() as u32
+}
+
+fn byte_index(line: &str, column: u32) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= column as usize {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Document>
+}
+
+impl Server {
+
+    fn document(&self, uri: &Url) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    fn request(&self, req: Request) -> Response {
+        let id = req.id.clone();
+        match req.method.as_str() {
+            HoverRequest::METHOD => match req.extract::<HoverParams>(HoverRequest::METHOD) {
+                Ok((id, params)) => {
+                    let position = params.text_document_position_params;
+                    let hover = self.document(&position.text_document.uri)
+                        .and_then(|doc| doc.hover(position.position))
+                        .map(|value| Hover {
+                            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+                            range: None
+                        });
+                    Response::new_ok(id, hover)
+                },
+                Err(err) => invalid_params(id, err)
+            },
+            GotoDefinition::METHOD => match req.extract::<GotoDefinitionParams>(GotoDefinition::METHOD) {
+                Ok((id, params)) => {
+                    let position = params.text_document_position_params;
+                    let uri = position.text_document.uri;
+                    let location = self.document(&uri)
+                        .and_then(|doc| doc.definition(position.position))
+                        .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range)));
+                    Response::new_ok(id, location)
+                },
+                Err(err) => invalid_params(id, err)
+            },
+            Completion::METHOD => match req.extract::<CompletionParams>(Completion::METHOD) {
+                Ok((id, params)) => {
+                    let items = self.document(&params.text_document_position.text_document.uri)
+                        .map(|doc| doc.completions())
+                        .unwrap_or_default();
+                    Response::new_ok(id, CompletionResponse::Array(items))
+                },
+                Err(err) => invalid_params(id, err)
+            },
+            CodeActionRequest::METHOD => match req.extract::<CodeActionParams>(CodeActionRequest::METHOD) {
+                Ok((id, params)) => {
+                    let uri = params.text_document.uri;
+                    let actions: Vec<CodeActionOrCommand> = self.document(&uri)
+                        .and_then(|doc| doc.evaluate(params.range.start.line as usize))
+                        .map(|edit| CodeActionOrCommand::CodeAction(CodeAction {
+                            title: "Evaluate query".to_owned(),
+                            kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                            edit: Some(WorkspaceEdit {
+                                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                                ..WorkspaceEdit::default()
+                            }),
+                            ..CodeAction::default()
+                        }))
+                        .into_iter()
+                        .collect();
+                    Response::new_ok(id, actions)
+                },
+                Err(err) => invalid_params(id, err)
+            },
+            _ => Response::new_err(id, ErrorCode::MethodNotFound as i32, format!("Unknown method {}", req.method))
+        }
+    }
+
+    /// Update the documents, returning the one that changed.
+    fn notification(&mut self, not: Notification) -> Option<Url> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = not.extract::<DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD).ok()?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), Document::parse(&params.text_document.text));
+                Some(uri)
+            },
+            DidChangeTextDocument::METHOD => {
+                let params = not.extract::<DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD).ok()?;
+                // Only full document sync is advertised, so the last change holds the whole text
+                let change = params.content_changes.into_iter().last()?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), Document::parse(&change.text));
+                Some(uri)
+            },
+            DidCloseTextDocument::METHOD => {
+                let params = not.extract::<DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                None
+            },
+            _ => None
+        }
+    }
+
+}
+
+fn invalid_params<E: std::fmt::Debug>(id: lsp_server::RequestId, err: E) -> Response {
+    Response::new_err(id, ErrorCode::InvalidParams as i32, format!("{:?}", err))
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// Serve a single client over stdin and stdout until it shuts down.
+pub fn run() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    serve(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+// Takes the connection by value so that it is closed before the IO threads are joined
+fn serve(connection: Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let mut server = Server::default();
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                connection.sender.send(Message::Response(server.request(req)))?;
+            },
+            Message::Notification(not) => {
+                if let Some(uri) = server.notification(not) {
+                    let diagnostics = server.documents[&uri].diagnostics();
+                    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+                    connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_owned(), params)))?;
+                }
+            },
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const SRC: &str = "[define-one] 1 -> S 0\n$x + 0 -> $x\nnot a rule\n1 + 0 -> ? // old result";
+
+    #[test]
+    fn test_diagnostics_and_definition() {
+        let doc = Document::parse(SRC);
+        let diagnostics = doc.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 2);
+        assert_eq!(doc.definition(Position::new(0, 5)), Some(Range::new(Position::new(0, 0), Position::new(0, 21))));
+        assert!(doc.hover(Position::new(1, 3)).unwrap().contains("$x + 0 -> $x"));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let doc = Document::parse(SRC);
+        assert_eq!(doc.evaluate(3).unwrap().new_text, "1 + 0 -> ? // = S 0");
+        assert!(doc.evaluate(1).is_none());
+    }
+
+    #[test]
+    fn test_evaluate_lazy() {
+        let src = "loop -> loop\nif true then $x else $y -> $x\n:lazy if _ then _ else _\nif true then 0 else loop -> ?";
+        let doc = Document::parse(src);
+        assert_eq!(doc.evaluate(3).unwrap().new_text, "if true then 0 else loop -> ? // = 0");
+    }
+
+}
@@ -1,25 +1,361 @@
-use std::io::{stdin, BufRead, BufReader};
 use std::error::Error;
-use std::env::args;
+use std::env::var;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
 use std::fs;
 
+use clap::{Parser, Subcommand, ValueEnum};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+
+use peano::completeness;
+use peano::format::format_source;
+use peano::lint::lint_source;
+use peano::reduce::Strategy;
 use peano::repl::*;
+use peano::signature::Signature;
+use peano::trs::{SourceError, Trs};
+
+const PROMPT: &str = "> ";
+const HISTORY_FILE: &str = ".peano_history";
+
+// Exit codes, so scripts can tell failures apart
+const EXIT_IO: u8 = 1;
+const EXIT_PARSE: u8 = 2;
+const EXIT_STEP_LIMIT: u8 = 3;
+const EXIT_ASSERTION: u8 = 4;
+
+/// A term rewriting engine. Starts the REPL when no subcommand is given.
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Rule file to load before starting the REPL
+    file: Option<PathBuf>,
+
+    /// Which redex to rewrite first
+    #[arg(long, global = true, default_value = "innermost")]
+    strategy: Strategy,
+
+    /// Give up on a query after this many steps
+    #[arg(long, global = true, default_value_t = 10_000)]
+    max_steps: usize,
+
+    /// How query results are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Only print the normal form of each query
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Share the terms that rules duplicate instead of copying them
+    #[arg(long, global = true)]
+    share: bool,
+
+    /// Print how often each rule was tried and applied, and the time it took
+    #[arg(long, global = true)]
+    profile: bool,
+
+    /// Seed for the random terms forall properties are checked with
+    #[arg(long, global = true, default_value_t = 0)]
+    seed: u64
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute every line of a file and exit
+    Run { file: PathBuf },
+    /// Normalize a single term
+    Normalize {
+        /// Rule file to normalize with
+        #[arg(short, long)]
+        rules: PathBuf,
+        expr: String
+    },
+    /// Check every assertion in the given files, skipping queries
+    Test {
+        #[arg(required = true)]
+        files: Vec<PathBuf>
+    },
+    /// Parse a file without running it
+    Check {
+        file: PathBuf,
+        /// Also warn about rules that nothing in the file produces a term for
+        #[arg(long)]
+        unused: bool
+    },
+    /// Print a file in canonical form
+    Fmt {
+        file: PathBuf,
+        /// Overwrite the file instead of printing it
+        #[arg(short, long)]
+        write: bool
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Json
+}
+
+/// Line editor support: symbol completion and multi-line
+/// input while parentheses are unbalanced.
+#[derive(Default)]
+struct ReplHelper {
+    words: Vec<String>
+}
+
+impl Completer for ReplHelper {
+
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '[' || c == '$')
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+        let candidates = self.words.iter()
+            .filter(|w| w.starts_with(prefix))
+            .map(|w| Pair { display: w.clone(), replacement: w.clone() })
+            .collect();
+        Ok((start, candidates))
+    }
+
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {
+
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+
+}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    var("HOME").ok().map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+impl Cli {
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let f = args().nth(1);
-    let mut repl = Repl::new();
-    if let Some(path) = f {
-        println!("<LOAD> '{}'", path);
-        let f = fs::File::open(path)?;
-        let b = BufReader::new(f);
-        for rline in b.lines() {
-            let line = rline?;
-            repl.exec(&line);
+    fn options(&self) -> Result<ReplOptions, String> {
+        let format = match self.format {
+            Format::Text => OutputFormat::Text,
+            #[cfg(feature = "serde")]
+            Format::Json => OutputFormat::Json,
+            #[cfg(not(feature = "serde"))]
+            Format::Json => return Err("JSON output requires the serde feature".to_owned())
+        };
+        Ok(ReplOptions {
+            strategy: self.strategy,
+            max_steps: Some(self.max_steps),
+            quiet: self.quiet,
+            skip_queries: false,
+            sharing: self.share,
+            profile: self.profile,
+            seed: self.seed,
+            format
+        })
+    }
+
+}
+
+fn read(path: &Path) -> Result<String, ExitCode> {
+    fs::read_to_string(path).map_err(|err| {
+        eprintln!("{}: {}", path.display(), err);
+        ExitCode::from(EXIT_IO)
+    })
+}
+
+fn exit_code(outcome: Outcome) -> ExitCode {
+    match outcome {
+        Outcome::Ok => ExitCode::SUCCESS,
+        Outcome::ParseError => ExitCode::from(EXIT_PARSE),
+        Outcome::StepLimit => ExitCode::from(EXIT_STEP_LIMIT),
+        Outcome::AssertionFailed => ExitCode::from(EXIT_ASSERTION)
+    }
+}
+
+/// Print errors the way compilers do, with 1-based lines and columns.
+fn report(path: &Path, errors: &[SourceError]) {
+    for error in errors {
+        eprintln!("{}:{}:{}: {}", path.display(), error.line + 1, error.column() + 1, error.error.kind());
+    }
+}
+
+/// Print the profile of `repl` if `--profile` was given.
+fn print_profile(repl: &Repl) {
+    if let Some(report) = repl.profile_report() {
+        eprint!("{}", report);
+    }
+}
+
+/// Execute each line of `src`, returning the first failure.
+fn run(repl: &mut Repl, src: &str) -> Outcome {
+    let mut result = Outcome::Ok;
+    for line in src.lines() {
+        let outcome = repl.exec(line);
+        if result == Outcome::Ok {
+            result = outcome;
+        }
+    }
+    result
+}
+
+/// Run the assertions in each file with a fresh set of rules.
+fn test(files: &[PathBuf], options: ReplOptions) -> Result<ExitCode, ExitCode> {
+    let options = ReplOptions { quiet: true, skip_queries: true, ..options };
+    let mut result = Outcome::Ok;
+    for path in files {
+        let mut repl = Repl::with_options(options.clone());
+        let outcome = run(&mut repl, &read(path)?);
+        if result == Outcome::Ok {
+            result = outcome;
+        }
+        let (passed, failed) = repl.assertions();
+        eprintln!("{}: {} passed, {} failed", path.display(), passed, failed);
+        print_profile(&repl);
+    }
+    Ok(exit_code(result))
+}
+
+fn check(path: &Path, unused: bool) -> Result<ExitCode, ExitCode> {
+    let src = read(path)?;
+    match Trs::from_source(&src) {
+        Ok(trs) => {
+            // Dead rules and terms that get stuck are not errors, but worth knowing about
+            if let Ok((symbols, warnings)) = lint_source(&src, unused) {
+                for warning in warnings {
+                    eprintln!("{}:{}:1: warning: {}", path.display(), warning.line + 1, warning.describe(&symbols));
+                }
+            }
+            for case in completeness::check(trs.rules(), Signature::infer(trs.rules()), trs.symbols()) {
+                eprintln!("{}: warning: no rule for {}", path.display(), case);
+            }
+            Ok(ExitCode::SUCCESS)
+        },
+        Err(errors) => {
+            report(path, &errors);
+            Ok(ExitCode::from(EXIT_PARSE))
+        }
+    }
+}
+
+fn fmt(path: &Path, write: bool) -> Result<ExitCode, ExitCode> {
+    match format_source(&read(path)?) {
+        Ok(formatted) if write => fs::write(path, formatted).map(|_| ExitCode::SUCCESS).map_err(|err| {
+            eprintln!("{}: {}", path.display(), err);
+            ExitCode::from(EXIT_IO)
+        }),
+        Ok(formatted) => {
+            print!("{}", formatted);
+            Ok(ExitCode::SUCCESS)
+        },
+        Err(errors) => {
+            report(path, &errors);
+            Ok(ExitCode::from(EXIT_PARSE))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let options = match cli.options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(EXIT_IO);
+        }
+    };
+    let result = match &cli.command {
+        Some(Command::Run { file }) => read(file).map(|src| {
+            let mut repl = Repl::with_options(options);
+            let outcome = run(&mut repl, &src);
+            print_profile(&repl);
+            exit_code(outcome)
+        }),
+        Some(Command::Normalize { rules, expr }) => read(rules).map(|src| match Trs::from_source(&src) {
+            Ok(trs) => {
+                let mut repl = Repl::from_trs(trs, options);
+                let outcome = repl.normalize(expr);
+                print_profile(&repl);
+                exit_code(outcome)
+            },
+            Err(errors) => {
+                report(rules, &errors);
+                ExitCode::from(EXIT_PARSE)
+            }
+        }),
+        Some(Command::Test { files }) => test(files, options),
+        Some(Command::Check { file, unused }) => check(file, *unused),
+        Some(Command::Fmt { file, write }) => fmt(file, *write),
+        None => interactive(cli.file.as_deref(), options).map_err(|err| {
+            eprintln!("{}", err);
+            ExitCode::from(EXIT_IO)
+        })
+    };
+    result.unwrap_or_else(|code| code)
+}
+
+fn interactive(file: Option<&Path>, options: ReplOptions) -> Result<ExitCode, Box<dyn Error>> {
+    let mut repl = Repl::with_options(options);
+    // Before loading, so Ctrl-C also stops a query of the loaded file
+    let interrupted = repl.interrupt_flag();
+    ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+    if let Some(path) = file {
+        println!("<LOAD> '{}'", path.display());
+        run(&mut repl, &fs::read_to_string(path)?);
+    }
+
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper::default()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        if let Some(helper) = editor.helper_mut() {
+            helper.words = repl.symbols().map(|s| s.to_owned()).collect();
+        }
+        match editor.readline(PROMPT) {
+            Ok(input) => {
+                editor.add_history_entry(input.as_str())?;
+                repl.exec(&input.replace('\n', " "));
+            },
+            // Ctrl-C at the prompt only discards the current input
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into())
         }
     }
-    for rline in stdin().lines() {
-        let line = rline?;
-        repl.exec(&line);
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
     }
-    Ok(())
+    print_profile(&repl);
+    Ok(ExitCode::SUCCESS)
 }
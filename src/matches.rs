@@ -1,19 +1,39 @@
-use crate::{expr::*, symbol_table::SymbolHandle};
+use crate::{binder::shift, expr::*, interpolate::Interpolate, symbol_table::{SymbolHandle, SymbolTable}};
 
 pub trait Matches {
 
-    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>) -> bool;
+    /// Match against `other`, which needs `symbols` to check name guards.
+    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>, symbols: &SymbolTable) -> bool;
 
 }
 
 impl Matches for Expression {
 
-    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>) -> bool {
+    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>, symbols: &SymbolTable) -> bool {
+        self.matches_at(other, bindings, 0, symbols) && distinct_bindings(bindings)
+    }
+
+}
+
+/// A distinct variable like `$$y` must be bound to a different term than
+/// every other variable. Checked once all variables are bound, so that
+/// the outcome does not depend on which variable was bound first. When
+/// the distinct variable comes last, as in `$x = $$y`, this is the same
+/// as checking it as soon as it is bound.
+fn distinct_bindings(bindings: &[VariableBinding<'_>]) -> bool {
+    bindings.iter()
+        .filter(|b| b.kind == VariableKind::Distinct)
+        .all(|d| bindings.iter().all(|b| b.var == d.var || b.expr != d.expr))
+}
+
+impl Expression {
+
+    fn matches_at<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>, depth: usize, symbols: &SymbolTable) -> bool {
         if self.0.len() != other.0.len() {
             return false;
         }
         for (a, b) in self.0.iter().zip(other.0.iter()) {
-            if !a.matches(b, bindings) {
+            if !a.matches_at(b, bindings, depth, symbols) {
                 return false;
             }
         }
@@ -24,12 +44,63 @@ impl Matches for Expression {
 
 pub struct VariableBinding<'t> {
     pub var: SymbolHandle,
-    pub expr: &'t Terminal
+    pub expr: &'t Terminal,
+    pub kind: VariableKind,
+    /// How many binders of the pattern the variable was matched under
+    pub depth: usize
+}
+
+impl VariableBinding<'_> {
+
+    /// The bound term as seen from under `depth` binders
+    /// instead of the binders it was matched under.
+    pub fn at_depth(&self, depth: usize) -> Terminal {
+        shift(self.expr, depth as isize - self.depth as isize, self.depth)
+    }
+
+}
+
+impl Guard {
+
+    pub fn accepts(&self, term: &Terminal, symbols: &SymbolTable) -> bool {
+        match (self, term) {
+            (Guard::Symbol, Terminal::Symbol(_)) => true,
+            (Guard::Compound, Terminal::Parentheses(_)) => true,
+            (Guard::Name(pattern), Terminal::Symbol(s)) => symbols.accepts(pattern, *s),
+            (Guard::OneOf(members), Terminal::Symbol(s)) => members.contains(s),
+            _ => false
+        }
+    }
+
+}
+
+impl Constraint {
+
+    /// Check the constraint once the left side of its rule has matched.
+    pub fn holds(&self, bindings: &[VariableBinding<'_>], symbols: &SymbolTable) -> bool {
+        match self {
+            Constraint::NotEqual(a, b) => a.interpolate(bindings) != b.interpolate(bindings),
+            Constraint::NotMatching(term, pattern) => {
+                let term = term.interpolate(bindings);
+                // Variables bound by the rule stand for their terms in the pattern
+                !pattern.interpolate(bindings).matches(&term, &mut vec![], symbols)
+            }
+        }
+    }
+
 }
 
 impl Matches for Terminal {
 
-    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>) -> bool {
+    fn matches<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>, symbols: &SymbolTable) -> bool {
+        self.matches_at(other, bindings, 0, symbols) && distinct_bindings(bindings)
+    }
+
+}
+
+impl Terminal {
+
+    fn matches_at<'t>(&self, other: &'t Self, bindings: &mut Vec<VariableBinding<'t>>, depth: usize, symbols: &SymbolTable) -> bool {
         match self {
             // Symbols must match exactly another symbol
             Terminal::Symbol(a) => {
@@ -40,36 +111,47 @@ impl Matches for Terminal {
                 }
             },
             // Variables match any terminal
-            Terminal::Variable(v, k) => {
-                
+            Terminal::Variable(v, k, guard) => {
+
+                if let Some(guard) = guard {
+                    if !guard.accepts(other, symbols) {
+                        return false;
+                    }
+                }
+
                 // Except when this variable has already been bound!
                 // In this case, the other expression must *equal* the bound
                 // expression exactly.
                 if let Some(binding) = bindings.iter().find(|b| b.var == *v) {
-                    return binding.expr == other;
+                    return if binding.depth == depth {
+                        binding.expr == other
+                    } else {
+                        binding.at_depth(depth) == *other
+                    };
                 }
 
-                // Additionally, if this variable is distinct, we must verify that the other expression
-                // has not already matched another variable.
-                // This enforces that $x and the distinct variable $$y
-                // only match distinct subexpressions.
-                if *k == VariableKind::Distinct {
-                    if bindings.iter().any(|b| b.expr == other) {
-                        return false;
-                    }
-                }
-                
-                bindings.push(VariableBinding { var: *v, expr: other });
+                bindings.push(VariableBinding { var: *v, expr: other, kind: *k, depth });
                 true
             },
             // Subexpressions in both parens must match
             Terminal::Parentheses(a) => {
                 if let Terminal::Parentheses(b) = other {
-                    a.matches(b, bindings)
+                    a.matches_at(b, bindings, depth, symbols)
                 } else {
                     false
                 }
-            }
+            },
+            // Binders match regardless of the names they were written with
+            Terminal::Binder(_, a) => {
+                if let Terminal::Binder(_, b) = other {
+                    a.matches_at(b, bindings, depth + 1, symbols)
+                } else {
+                    false
+                }
+            },
+            Terminal::Bound(a) => other == &Terminal::Bound(*a),
+            // Substitutions only make sense on the right side of a rule
+            Terminal::Substitution(..) => false
         }
     }
 
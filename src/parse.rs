@@ -1,5 +1,5 @@
 use std::{fmt::Display, error::Error};
-use crate::{expr::*, symbol_table::SymbolTable};
+use crate::{binder::*, expr::*, reduce::{ReplacementMap, Slot}, signature::{Shape, Signature}, strategy::Combinator, symbol_table::{NamePattern, SymbolTable}};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Token {
@@ -23,11 +23,48 @@ impl Display for Token {
 #[derive(Debug)]
 pub struct ParseError {
     idx: usize,
+    // How much input was left from the point of the error,
+    // used to find the column relative to the start of the input.
+    rest: usize,
     error: ErrorKind
 }
 
 impl Error for ParseError {}
 
+impl ParseError {
+
+    fn at(s: &str, idx: usize, error: ErrorKind) -> Self {
+        ParseError { idx, rest: s.len().saturating_sub(idx), error }
+    }
+
+    /// An error found after parsing, such as an invalid rule, at `column`.
+    pub fn new(column: usize, error: ErrorKind) -> Self {
+        ParseError { idx: column, rest: 0, error }
+    }
+
+    /// Point this error at its column within `s`, the complete parser input.
+    fn located(mut self, s: &str) -> Self {
+        self.idx = s.len().saturating_sub(self.rest);
+        self
+    }
+
+    /// The column the error was found at, starting from 0.
+    pub fn column(&self) -> usize {
+        self.idx
+    }
+
+    /// The column in UTF-16 code units, as JavaScript counts them,
+    /// where `s` is the input the error was found in.
+    pub fn utf16_column(&self, s: &str) -> usize {
+        s.get(..self.idx).unwrap_or(s).encode_utf16().count()
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.error
+    }
+
+}
+
 pub type ParseResult<'s, T> = Result<(T, &'s str), ParseError>;
 
 impl Display for ParseError {
@@ -41,7 +78,12 @@ impl Display for ParseError {
 pub enum ErrorKind {
     ReservedSymbol(&'static str),
     UnexpectedEoF,
-    ExpectedToken(Token)
+    ExpectedToken(Token),
+    UnknownGuard(String),
+    InvalidRegex(String),
+    UnknownCombinator(String, usize),
+    /// A rule that parsed but failed `validate::validate`, described
+    InvalidRule(String)
 }
 
 impl Display for ErrorKind {
@@ -49,7 +91,11 @@ impl Display for ErrorKind {
         match self {
             ErrorKind::ReservedSymbol(s) => write!(f, "\"{}\" is a reserved symbol", s),
             ErrorKind::UnexpectedEoF => write!(f, "Incomplete statement"),
-            ErrorKind::ExpectedToken(t) => write!(f, "Expected token {}", t)
+            ErrorKind::ExpectedToken(t) => write!(f, "Expected token {}", t),
+            ErrorKind::UnknownGuard(g) => write!(f, "Unknown guard \"{}\", expected sym, paren, int or /regex/", g),
+            ErrorKind::InvalidRegex(e) => write!(f, "Invalid regex: {}", e),
+            ErrorKind::UnknownCombinator(c, n) => write!(f, "No combinator \"{}\" with {} arguments", c, n),
+            ErrorKind::InvalidRule(description) => write!(f, "Invalid rule: {}", description)
         }
     }
 }
@@ -58,17 +104,27 @@ pub trait TryParse: Sized {
 
     fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self>;
 
-    fn parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> Result<Self, ParseError> {
-        let (parsed, _s) = Self::try_parse(s, symbols)?;
+    fn parse(s: &str, symbols: &mut SymbolTable) -> Result<Self, ParseError> {
+        let (parsed, _s) = Self::try_parse(s, symbols).map_err(|e| e.located(s))?;
+        Ok(parsed)
+    }
+
+    /// Like `parse`, but fails unless the whole input was consumed.
+    fn parse_complete(s: &str, symbols: &mut SymbolTable) -> Result<Self, ParseError> {
+        let (parsed, rest) = Self::try_parse(s, symbols).map_err(|e| e.located(s))?;
+        let trimmed = rest.trim_start();
+        if !trimmed.is_empty() {
+            return Err(ParseError::at(trimmed, 0, ErrorKind::ExpectedToken(Token::Eof)).located(s));
+        }
         Ok(parsed)
     }
 
 }
 
 fn take_until<'s, F: Fn(char) -> bool>(s: &'s str, predicate: F) -> ParseResult<'s, &'s str> {
-    if let Some(idx) = s.chars().position(predicate) {
+    if let Some((idx, _)) = s.char_indices().find(|(_, c)| predicate(*c)) {
         if idx == 0 {
-            return Err(ParseError { idx, error: ErrorKind::ExpectedToken(Token::Symbol)})
+            return Err(ParseError::at(s, idx, ErrorKind::ExpectedToken(Token::Symbol)))
         } else {
             return Ok((&s[0..idx], &s[idx..]))
         }
@@ -78,11 +134,11 @@ fn take_until<'s, F: Fn(char) -> bool>(s: &'s str, predicate: F) -> ParseResult<
 
 fn take_const<'s>(s: &'s str, c: &'static str) -> ParseResult<'s, &'s str> {
     if s.len() < c.len() {
-        return Err(ParseError { idx: 0, error: ErrorKind::UnexpectedEoF })
+        return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF))
     }
     for (i, (a, b)) in s.chars().zip(c.chars()).enumerate() {
         if a != b {
-            return Err(ParseError { idx: i, error: ErrorKind::ExpectedToken(Token::Constant(c)) })
+            return Err(ParseError::at(s, i, ErrorKind::ExpectedToken(Token::Constant(c))))
         }
     }
     Ok((c, &s[c.len()..]))
@@ -102,59 +158,164 @@ impl Terminal {
         let (distinct, s) = optionally(s, take_const(s, "$"))?;
         let (n, s) = take_until(s, |c| !c.is_alphanumeric())?;
         let kind = if distinct.is_some() { VariableKind::Distinct } else { VariableKind::Any };
-        Ok((Terminal::Variable(symbols.handle(n), kind), s))
+        // $b[t] substitutes t for the variable of the binder $b was matched under
+        if let Ok((_, rest)) = take_const(s, "[") {
+            let (arg, rest) = Terminal::try_parse_until(rest, symbols, &[']'])?;
+            let (_, rest) = take_const(rest, "]")?;
+            return Ok((Terminal::Substitution(symbols.handle(n), Box::new(arg)), rest));
+        }
+        let (guard, s) = Terminal::try_parse_guard(s, symbols)?;
+        Ok((Terminal::Variable(symbols.handle(n), kind, guard.map(Box::new)), s))
+    }
+
+    /// An optional guard after a variable, `:sym`, `:paren`, `:int`,
+    /// `:/regex/` or ` in {a, b, c}`.
+    fn try_parse_guard<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Option<Guard>> {
+        if let Ok((_colon, rest)) = take_const(s, ":") {
+            if let Ok((_slash, rest)) = take_const(rest, "/") {
+                // The regex ends at the first slash that is not escaped
+                let end = rest.char_indices()
+                    .find(|&(idx, c)| c == '/' && !rest[..idx].ends_with('\\'))
+                    .map(|(idx, _)| idx)
+                    .ok_or_else(|| ParseError::at(rest, rest.len(), ErrorKind::UnexpectedEoF))?;
+                let pattern = symbols.regex(&rest[..end]).map_err(|e| {
+                    // The last line of the message names the problem
+                    let message = e.to_string();
+                    let reason = message.lines().last().unwrap_or_default().trim_start_matches("error: ");
+                    ParseError::at(rest, 0, ErrorKind::InvalidRegex(reason.to_owned()))
+                })?;
+                return Ok((Some(Guard::Name(pattern)), &rest[end + 1..]));
+            }
+            let (name, after) = take_until(rest, |c| !c.is_alphanumeric())?;
+            let guard = match name {
+                "sym" => Guard::Symbol,
+                "paren" => Guard::Compound,
+                "int" => Guard::Name(NamePattern::Integer),
+                _ => return Err(ParseError::at(rest, 0, ErrorKind::UnknownGuard(name.to_owned())))
+            };
+            return Ok((Some(guard), after));
+        }
+        // Only a set of symbols after `in` makes it a guard, `in` may be a symbol
+        let (_w, rest) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let Ok((_in, rest)) = take_const(rest, "in") else {
+            return Ok((None, s));
+        };
+        let (_w, rest) = optionally(rest, take_until(rest, |c| !c.is_whitespace()))?;
+        let Ok((_brace, mut rest)) = take_const(rest, "{") else {
+            return Ok((None, s));
+        };
+        let mut members = vec![];
+        loop {
+            let (_w, r) = optionally(rest, take_until(rest, |c| !c.is_whitespace()))?;
+            let (member, r) = take_until(r, |c| c.is_whitespace() || c == ',' || c == '}')?;
+            members.push(symbols.handle(member));
+            let (_w, r) = optionally(r, take_until(r, |c| !c.is_whitespace()))?;
+            if let Ok((_comma, r)) = take_const(r, ",") {
+                rest = r;
+            } else {
+                let (_brace, r) = take_const(r, "}")?;
+                return Ok((Some(Guard::OneOf(members)), r));
+            }
+        }
     }
 
-    fn try_parse_symbol<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Terminal> {
-        let (symbol, s) = take_until(s, |c| c.is_whitespace()
+    fn try_parse_binder<'s>(s: &'s str, symbols: &mut SymbolTable, stop: &[char]) -> ParseResult<'s, Terminal> {
+        let (_lambda, s) = take_const(s, "\\")?;
+        let (name, s) = take_until(s, |c| c == '.' || c.is_whitespace() || c == '(' || c == ')')?;
+        let (_dot, s) = take_const(s, ".")?;
+        // The body extends as far to the right as possible
+        let (body, s) = Expression::try_parse_until(s, symbols, stop)?;
+        if body.0.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF));
+        }
+        let name = symbols.handle(name);
+        Ok((Terminal::Binder(Hint(name), Box::new(abstract_name(&expression_body(body), name))), s))
+    }
+
+    fn try_parse_symbol<'s>(s: &'s str, symbols: &mut SymbolTable, stop: &[char]) -> ParseResult<'s, Terminal> {
+        let (symbol, rest) = take_until(s, |c| c.is_whitespace()
                                                                       || c == '('
-                                                                      || c == ')')?;
-        if symbol == "->" {
-            return Err(ParseError{ idx: 0, error: ErrorKind::ReservedSymbol("->") });
+                                                                      || c == ')'
+                                                                      || stop.contains(&c))?;
+        for reserved in ["->", "->*", "-/->*", "where"] {
+            if symbol == reserved {
+                return Err(ParseError::at(s, 0, ErrorKind::ReservedSymbol(reserved)));
+            }
         }
         if symbol == "//" {
-            return Err(ParseError { idx: 0, error: ErrorKind::ReservedSymbol("//") });
+            return Err(ParseError::at(s, 0, ErrorKind::ReservedSymbol("//")));
         }
-        Ok((Terminal::Symbol(symbols.handle(symbol)), s))
+        Ok((Terminal::Symbol(symbols.handle(symbol)), rest))
     }
 
     fn try_parse_parens<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Terminal> {
         let (_paren, s) = take_const(s, "(")?;
         let (expr, s) = Expression::try_parse(s, symbols)?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
         let (_paren, s) = take_const(s, ")")?;
+        // Parentheses around a single term, like around a binder, only group it
+        if expr.0.len() == 1 {
+            return Ok((expr.0.into_iter().next().unwrap(), s));
+        }
         Ok((Terminal::Parentheses(expr), s))
     }
 
 }
 
-impl TryParse for Terminal {
+impl Terminal {
 
-    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+    /// Like `try_parse`, but a symbol also ends at any of `stop`, the
+    /// characters that may follow the term in the enclosing statement.
+    /// Inside parentheses they are part of symbols again.
+    fn try_parse_until<'s>(s: &'s str, symbols: &mut SymbolTable, stop: &[char]) -> ParseResult<'s, Terminal> {
+        // Keep the error of a malformed guard instead of reading a symbol
+        if s.starts_with("$$") || (s.starts_with('$') && s[1..].starts_with(char::is_alphanumeric)) {
+            return Terminal::try_parse_variable(s, symbols);
+        }
         Terminal::try_parse_variable(s, symbols)
+            .or_else(|_| Terminal::try_parse_binder(s, symbols, stop))
             .or_else(|_| Terminal::try_parse_parens(s, symbols))
-            .or_else(|_| Terminal::try_parse_symbol(s, symbols))
+            .or_else(|_| Terminal::try_parse_symbol(s, symbols, stop))
             // TODO: Map the error here to better explain what is being parsed.
     }
 
 }
 
-impl TryParse for Expression {
+impl TryParse for Terminal {
 
     fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        Terminal::try_parse_until(s, symbols, &[])
+    }
+
+}
+
+impl Expression {
+
+    /// Like `try_parse`, with the symbols of each term ending at `stop`,
+    /// see `Terminal::try_parse_until`.
+    fn try_parse_until<'s>(s: &'s str, symbols: &mut SymbolTable, stop: &[char]) -> ParseResult<'s, Expression> {
         let mut parsed = Vec::<Terminal>::new();
         let mut rem = s;
         loop {
             if rem.is_empty() { break; }
             let (_w, s) = take_until(rem,|c| !c.is_whitespace())
                             .unwrap_or(("", rem));
-            match Terminal::try_parse(s, symbols) {
+            match Terminal::try_parse_until(s, symbols, stop) {
                 Ok((term, s)) => {
                     rem = s;
                     parsed.push(term);
                 },
-                Err(_) => {
-                    return Ok((Expression(parsed), rem));
-                }
+                // A malformed guard is an error rather than the end of the expression
+                Err(e) if matches!(e.error, ErrorKind::UnknownGuard(_) | ErrorKind::InvalidRegex(_)) => {
+                    return Err(e);
+                },
+                Err(_) => break
+            }
+        }
+        // `(a b)` on its own is the same term as `a b`
+        if let [Terminal::Parentheses(_)] = parsed.as_slice() {
+            if let Some(Terminal::Parentheses(inner)) = parsed.pop() {
+                return Ok((inner, rem));
             }
         }
         Ok((Expression(parsed), rem))
@@ -162,6 +323,14 @@ impl TryParse for Expression {
 
 }
 
+impl TryParse for Expression {
+
+    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        Expression::try_parse_until(s, symbols, &[])
+    }
+
+}
+
 impl Statement {
 
     fn try_parse_rewrite<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
@@ -170,7 +339,161 @@ impl Statement {
         let (_arrow, s) = take_const(s, "->")?;
         let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
         let (right, s) = Expression::try_parse(s, symbols)?;
-        Ok((Statement::Rewrite(left, right), s))
+        let (_w, rest) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let Ok((_where, rest)) = take_const(rest, "where") else {
+            return Ok((Statement::Rewrite(left, right, vec![]), s));
+        };
+        let mut constraints = vec![];
+        let mut rest = rest;
+        loop {
+            let (_w, r) = take_until(rest, |c| !c.is_whitespace())?;
+            let (constraint, r) = Constraint::try_parse(r, symbols)?;
+            constraints.push(constraint);
+            let (_w, r) = optionally(r, take_until(r, |c| !c.is_whitespace()))?;
+            match take_const(r, ",") {
+                Ok((_comma, r)) => rest = r,
+                Err(_) => return Ok((Statement::Rewrite(left, right, constraints), r))
+            }
+        }
+    }
+
+    fn try_parse_assert<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "assert")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        Statement::try_parse_reaches(s, symbols)
+    }
+
+    /// `a ->* b` or `a -/->* b`, the part of an assertion after `assert`.
+    fn try_parse_reaches<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (left, s) = Expression::try_parse(s, symbols)?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (negated, s) = optionally(s, take_const(s, "-/->*"))?;
+        let s = if negated.is_none() { take_const(s, "->*")?.1 } else { s };
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (right, s) = Expression::try_parse(s, symbols)?;
+        if right.0.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF));
+        }
+        match negated {
+            Some(_) => Ok((Statement::NotReaches(left, right), s)),
+            None => Ok((Statement::Reaches(left, right), s))
+        }
+    }
+
+    fn try_parse_expect<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "expect")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        Statement::try_parse_normalizes(s, symbols)
+    }
+
+    /// `a -> ? b`, the part of an expectation after `expect`.
+    fn try_parse_normalizes<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (left, s) = Expression::try_parse(s, symbols)?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_arrow, s) = take_const(s, "->")?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_query, s) = take_const(s, "?")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (right, s) = Expression::try_parse(s, symbols)?;
+        if right.0.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF));
+        }
+        Ok((Statement::Expect(left, right), s))
+    }
+
+    fn try_parse_property<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, mut s) = take_const(s, "forall")?;
+        let mut vars = vec![];
+        loop {
+            let (_w, rest) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+            if let Ok((_colon, rest)) = take_const(rest, ":") {
+                s = rest;
+                break;
+            }
+            let (_d, rest) = take_const(rest, "$")?;
+            let (name, rest) = take_until(rest, |c| !c.is_alphanumeric())?;
+            vars.push(symbols.handle(name));
+            s = rest;
+        }
+        if vars.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::ExpectedToken(Token::Constant("$"))));
+        }
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (body, s) = Statement::try_parse_reaches(s, symbols)
+            .or_else(|_| Statement::try_parse_normalizes(s, symbols))?;
+        Ok((Statement::Property(vars, Box::new(body)), s))
+    }
+
+    fn try_parse_signature<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "signature")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (signature, s) = Signature::try_parse(s, symbols)?;
+        Ok((Statement::Signature(signature), s))
+    }
+
+    fn try_parse_proof<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "proof")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (start, s) = Expression::try_parse(s, symbols)?;
+        if start.0.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF));
+        }
+        Ok((Statement::Proof(start), s))
+    }
+
+    fn try_parse_step<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_eq, s) = take_const(s, "=")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (term, s) = Expression::try_parse_until(s, symbols, &['{'])?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_brace, s) = take_const(s, "{")?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_by, s) = take_const(s, "by")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (label, s) = take_until(s, |c| c == '}' || c.is_whitespace())?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_brace, s) = take_const(s, "}")?;
+        Ok((Statement::Step(term, symbols.handle(label)), s))
+    }
+
+    fn try_parse_qed<'s>(s: &'s str, _symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "qed")?;
+        if s.starts_with(|c: char| !c.is_whitespace()) {
+            return Err(ParseError::at(s, 0, ErrorKind::ExpectedToken(Token::Eof)));
+        }
+        Ok((Statement::Qed, s))
+    }
+
+    fn try_parse_strategy<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "strategy")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (name, s) = take_until(s, |c| c.is_whitespace() || c == '=')?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_eq, s) = take_const(s, "=")?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (strategy, s) = Combinator::try_parse(s, symbols)?;
+        Ok((Statement::Strategy(symbols.handle(name), strategy), s))
+    }
+
+    fn try_parse_apply<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, "apply")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (strategy, s) = Combinator::try_parse(s, symbols)?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (_colon, s) = take_const(s, ":")?;
+        let (_w, s) = optionally(s, take_until(s, |c| !c.is_whitespace()))?;
+        let (term, s) = Expression::try_parse(s, symbols)?;
+        if term.0.is_empty() {
+            return Err(ParseError::at(s, 0, ErrorKind::UnexpectedEoF));
+        }
+        Ok((Statement::Apply(strategy, term), s))
+    }
+
+    fn try_parse_lazy<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
+        let (_keyword, s) = take_const(s, ":lazy")?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (map, s) = ReplacementMap::try_parse(s, symbols)?;
+        Ok((Statement::Lazy(map), s))
     }
 
     fn try_parse_noop<'s>(s: &'s str, _symbols: &mut SymbolTable) -> ParseResult<'s, Statement> {
@@ -178,19 +501,160 @@ impl Statement {
         if s.is_empty() || s.starts_with("//") {
             Ok((Statement::Noop, s))
         } else {
-            Err(ParseError { idx: 0, error: ErrorKind::ExpectedToken(Token::Eof)})
+            Err(ParseError::at(s, 0, ErrorKind::ExpectedToken(Token::Eof)))
         }
     }
 
-    
+}
 
+/// A statement that starts with a keyword only ends the line if nothing but
+/// a comment follows it, otherwise the line may still be a rule like `qed -> done`.
+fn ends_line<'s>(parsed: ParseResult<'s, Statement>) -> ParseResult<'s, Statement> {
+    let (statement, s) = parsed?;
+    let rest = s.trim_start();
+    if rest.is_empty() || rest.starts_with("//") {
+        Ok((statement, s))
+    } else {
+        Err(ParseError::at(rest, 0, ErrorKind::ExpectedToken(Token::Eof)))
+    }
 }
 
 impl TryParse for Statement {
 
     fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
         Statement::try_parse_noop(s, symbols)
-            .or_else(|_| Statement::try_parse_rewrite(s, symbols))
+            .or_else(|_| ends_line(Statement::try_parse_assert(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_expect(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_proof(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_step(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_qed(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_strategy(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_apply(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_lazy(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_property(s, symbols)))
+            .or_else(|_| ends_line(Statement::try_parse_signature(s, symbols)))
+            .or_else(|e| match e.error {
+                // The keyword was there, the strategy is what's wrong
+                ErrorKind::UnknownCombinator(..) => Err(e),
+                _ => Statement::try_parse_rewrite(s, symbols)
+            })
+    }
+
+}
+
+impl TryParse for Constraint {
+
+    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        let (left, s) = Terminal::try_parse_until(s, symbols, &[','])?;
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        let (negated_match, s) = optionally(s, take_const(s, "!~"))?;
+        let s = if negated_match.is_none() { take_const(s, "!=")?.1 } else { s };
+        let (_w, s) = take_until(s, |c| !c.is_whitespace())?;
+        // Constraints are separated by commas
+        let (right, s) = Terminal::try_parse_until(s, symbols, &[','])?;
+        match negated_match {
+            Some(_) => Ok((Constraint::NotMatching(left, right), s)),
+            None => Ok((Constraint::NotEqual(left, right), s))
+        }
+    }
+
+}
+
+impl TryParse for Combinator {
+
+    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        let (name, after) = take_until(s, |c| c.is_whitespace() || c == '(' || c == ')' || c == ',' || c == ':')?;
+        let Ok((_paren, mut rest)) = take_const(after, "(") else {
+            return Ok((match name {
+                "id" => Combinator::Id,
+                "fail" => Combinator::Fail,
+                _ => Combinator::Call(symbols.handle(name))
+            }, after));
+        };
+        let mut arguments = vec![];
+        loop {
+            let (_w, r) = optionally(rest, take_until(rest, |c| !c.is_whitespace()))?;
+            let (argument, r) = Combinator::try_parse(r, symbols)?;
+            arguments.push(argument);
+            let (_w, r) = optionally(r, take_until(r, |c| !c.is_whitespace()))?;
+            match take_const(r, ",") {
+                Ok((_comma, r)) => rest = r,
+                Err(_) => {
+                    rest = take_const(r, ")")?.1;
+                    break;
+                }
+            }
+        }
+        let combinator = match (name, arguments.len()) {
+            ("seq", _) => Combinator::Seq(arguments),
+            ("choice", _) => Combinator::Choice(arguments),
+            ("oneOf", _) => Combinator::OneOf(arguments),
+            ("try", 1) => Combinator::Try(Box::new(arguments.remove(0))),
+            ("repeat", 1) => Combinator::Repeat(Box::new(arguments.remove(0))),
+            ("all", 1) => Combinator::All(Box::new(arguments.remove(0))),
+            ("one", 1) => Combinator::One(Box::new(arguments.remove(0))),
+            ("bottomup", 1) => Combinator::BottomUp(Box::new(arguments.remove(0))),
+            ("topdown", 1) => Combinator::TopDown(Box::new(arguments.remove(0))),
+            ("innermost", 1) => Combinator::Innermost(Box::new(arguments.remove(0))),
+            _ => return Err(ParseError::at(s, 0, ErrorKind::UnknownCombinator(name.to_owned(), arguments.len())))
+        };
+        Ok((combinator, rest))
+    }
+
+}
+
+impl TryParse for ReplacementMap {
+
+    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        let (pattern, rest) = Expression::try_parse(s, symbols)?;
+        let mut slots = vec![];
+        for term in pattern.0 {
+            let Terminal::Symbol(handle) = term else {
+                return Err(ParseError::at(s, 0, ErrorKind::ExpectedToken(Token::Symbol)));
+            };
+            slots.push(match symbols.lookup(handle) {
+                "_" => Slot::Frozen,
+                "!" => Slot::Active,
+                _ => Slot::Symbol(handle)
+            });
+        }
+        // A declaration without arguments would have no effect
+        if slots.iter().all(|slot| matches!(slot, Slot::Symbol(_))) {
+            return Err(ParseError::at(rest, 0, ErrorKind::ExpectedToken(Token::Constant("_"))));
+        }
+        Ok((ReplacementMap(slots), rest))
+    }
+
+}
+
+impl TryParse for Signature {
+
+    fn try_parse<'s>(s: &'s str, symbols: &mut SymbolTable) -> ParseResult<'s, Self> {
+        let mut signature = Signature::default();
+        let mut s = s;
+        loop {
+            let (item, rest) = Expression::try_parse_until(s, symbols, &[','])?;
+            let mut slots = vec![];
+            for term in &item.0 {
+                let Terminal::Symbol(handle) = term else {
+                    return Err(ParseError::at(s, 0, ErrorKind::ExpectedToken(Token::Symbol)));
+                };
+                slots.push(Some(*handle).filter(|h| symbols.lookup(*h) != "_"));
+            }
+            match slots.as_slice() {
+                [] | [None] => return Err(ParseError::at(rest, 0, ErrorKind::ExpectedToken(Token::Symbol))),
+                [Some(constant)] => signature.add_constant(*constant),
+                _ if slots.iter().all(Option::is_some) => {
+                    return Err(ParseError::at(rest, 0, ErrorKind::ExpectedToken(Token::Constant("_"))));
+                },
+                _ => signature.add_shape(Shape(slots))
+            }
+            let (_w, rest) = optionally(rest, take_until(rest, |c| !c.is_whitespace()))?;
+            match take_const(rest, ",") {
+                Ok((_comma, rest)) => s = rest,
+                Err(_) => return Ok((signature, rest))
+            }
+        }
     }
 
 }
@@ -0,0 +1,275 @@
+use std::fmt::Write;
+
+use crate::{expr::*, symbol_table::SymbolTable};
+
+/// A document in the style of Wadler's "A prettier printer".
+/// Each `Line` in a `Group` is rendered as a single space if the
+/// whole group fits on the current line, otherwise as a newline
+/// followed by the current indentation.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    Line,
+    Nest(usize, Box<Doc>),
+    Concat(Box<Doc>, Box<Doc>),
+    Group(Box<Doc>)
+}
+
+impl Doc {
+
+    pub fn text(s: impl Into<String>) -> Doc {
+        Doc::Text(s.into())
+    }
+
+    pub fn nest(self, indent: usize) -> Doc {
+        Doc::Nest(indent, Box::new(self))
+    }
+
+    pub fn group(self) -> Doc {
+        Doc::Group(Box::new(self))
+    }
+
+    pub fn append(self, other: Doc) -> Doc {
+        Doc::Concat(Box::new(self), Box::new(other))
+    }
+
+    /// Lay out this document so that it fits in `width` columns where possible.
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut column = 0;
+        let mut stack = vec![(0, Mode::Break, self)];
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => {},
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    column += s.chars().count();
+                },
+                Doc::Line => match mode {
+                    Mode::Flat => {
+                        out.push(' ');
+                        column += 1;
+                    },
+                    Mode::Break => {
+                        out.push('\n');
+                        out.extend(std::iter::repeat_n(' ', indent));
+                        column = indent;
+                    }
+                },
+                Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+                Doc::Concat(a, b) => {
+                    stack.push((indent, mode, b));
+                    stack.push((indent, mode, a));
+                },
+                Doc::Group(d) => {
+                    let remaining = width as isize - column as isize;
+                    let mode = if mode == Mode::Flat || fits(remaining, d, &stack) { Mode::Flat } else { Mode::Break };
+                    stack.push((indent, mode, d));
+                }
+            }
+        }
+        out
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break
+}
+
+/// Does `doc` fit in `remaining` columns when laid out flat, together with
+/// whatever follows it in `rest` up to the next line break? Otherwise the
+/// closing parentheses of enclosing groups could run past the width.
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    loop {
+        if remaining < 0 {
+            return false;
+        }
+        let Some((mode, doc)) = stack.pop().or_else(|| rest.next().map(|&(_, mode, doc)| (mode, doc))) else {
+            return true;
+        };
+        match doc {
+            Doc::Nil => {},
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::Nest(_, d) => stack.push((mode, d)),
+            Doc::Group(d) => stack.push((Mode::Flat, d)),
+            Doc::Concat(a, b) => {
+                stack.push((mode, b));
+                stack.push((mode, a));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrettyOptions {
+    /// The target line width
+    pub width: usize,
+    /// How far the contents of a broken `Parentheses` are indented
+    pub indent: usize,
+    /// Drop parentheses that do not change how the term reads,
+    /// i.e. around a single terminal and around another pair of parentheses.
+    pub minimal_parens: bool
+}
+
+impl Default for PrettyOptions {
+
+    fn default() -> Self {
+        PrettyOptions { width: 80, indent: 2, minimal_parens: false }
+    }
+
+}
+
+impl Expression {
+
+    /// Render this expression as a document that breaks nested
+    /// parentheses across lines once they no longer fit.
+    pub fn to_doc(&self, symbols: &SymbolTable, options: &PrettyOptions) -> Doc {
+        let mut terms = self.0.as_slice();
+        if options.minimal_parens {
+            terms = strip_parens(terms);
+        }
+        terms_to_doc(terms, symbols, options)
+    }
+
+    pub fn pretty(&self, symbols: &SymbolTable, options: &PrettyOptions) -> String {
+        self.to_doc(symbols, options).render(options.width)
+    }
+
+    /// An indented view of the syntax tree, one terminal per line.
+    pub fn tree(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        write_tree(&mut out, symbols, &self.0, 0);
+        out
+    }
+
+}
+
+impl Terminal {
+
+    pub fn to_doc(&self, symbols: &SymbolTable, options: &PrettyOptions) -> Doc {
+        match self {
+            Terminal::Parentheses(e) => {
+                let mut terms = e.0.as_slice();
+                if options.minimal_parens {
+                    terms = strip_parens(terms);
+                    if terms.len() == 1 {
+                        return terms[0].to_doc(symbols, options);
+                    }
+                }
+                Doc::text("(")
+                    .append(terms_to_doc(terms, symbols, options).nest(options.indent))
+                    .append(Doc::text(")"))
+                    .group()
+            },
+            _ => Doc::text(format!("{}", self.bind(symbols)))
+        }
+    }
+
+}
+
+fn terms_to_doc(terms: &[Terminal], symbols: &SymbolTable, options: &PrettyOptions) -> Doc {
+    let mut doc = Doc::Nil;
+    for (idx, term) in terms.iter().enumerate() {
+        if idx > 0 {
+            doc = doc.append(Doc::Line);
+        }
+        // A binder extends as far to the right as possible
+        if matches!(term, Terminal::Binder(..)) && idx + 1 < terms.len() {
+            doc = doc.append(Doc::text(format!("({})", term.bind(symbols))));
+        } else {
+            doc = doc.append(term.to_doc(symbols, options));
+        }
+    }
+    doc.group()
+}
+
+/// Unwrap `((...))` down to the innermost parenthesized terms.
+fn strip_parens(mut terms: &[Terminal]) -> &[Terminal] {
+    while let [Terminal::Parentheses(e)] = terms {
+        terms = &e.0;
+    }
+    terms
+}
+
+fn write_tree(out: &mut String, symbols: &SymbolTable, terms: &[Terminal], depth: usize) {
+    for term in terms {
+        let pad = "  ".repeat(depth);
+        // Writing to a String never fails
+        let _ = match term {
+            Terminal::Symbol(s) => writeln!(out, "{}Symbol {}", pad, symbols.lookup(*s)),
+            Terminal::Variable(..) => writeln!(out, "{}Variable {}", pad, term.bind(symbols)),
+            Terminal::Parentheses(e) => {
+                let _ = writeln!(out, "{}Parentheses", pad);
+                write_tree(out, symbols, &e.0, depth + 1);
+                Ok(())
+            },
+            Terminal::Binder(hint, body) => {
+                let _ = writeln!(out, "{}Binder {}", pad, symbols.lookup(hint.0));
+                write_tree(out, symbols, std::slice::from_ref(body.as_ref()), depth + 1);
+                Ok(())
+            },
+            Terminal::Bound(idx) => writeln!(out, "{}Bound {}", pad, idx),
+            Terminal::Substitution(v, arg) => {
+                let _ = writeln!(out, "{}Substitution ${}", pad, symbols.lookup(*v));
+                write_tree(out, symbols, std::slice::from_ref(arg.as_ref()), depth + 1);
+                Ok(())
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_pretty_fits_on_one_line() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols S (S (S 0)));
+        assert_eq!(e.pretty(&symbols, &PrettyOptions::default()), "S (S (S 0))");
+    }
+
+    #[test]
+    fn test_pretty_breaks_nested_parens() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols TAPE ((b (a ())) 0 ()));
+        let options = PrettyOptions { width: 12, ..PrettyOptions::default() };
+        assert_eq!(e.pretty(&symbols, &options), "TAPE\n((b (a ()))\n  0\n  ())");
+    }
+
+    #[test]
+    fn test_pretty_counts_closing_parens() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols (aaaa bbbbb (c d)));
+        // The inner terms fit in 17 columns, but not with the closing parenthesis
+        let options = PrettyOptions { width: 17, ..PrettyOptions::default() };
+        assert_eq!(e.pretty(&symbols, &options), "(aaaa\n  bbbbb\n  (c d))");
+        let options = PrettyOptions { width: 18, ..PrettyOptions::default() };
+        assert_eq!(e.pretty(&symbols, &options), "(aaaa bbbbb (c d))");
+    }
+
+    #[test]
+    fn test_pretty_minimal_parens() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols ((a b)) + (c (d)));
+        let options = PrettyOptions { minimal_parens: true, ..PrettyOptions::default() };
+        assert_eq!(e.pretty(&symbols, &options), "(a b) + (c d)");
+    }
+
+    #[test]
+    fn test_tree() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols S ([x]));
+        assert_eq!(e.tree(&symbols), "Symbol S\nParentheses\n  Variable $x\n");
+    }
+
+}
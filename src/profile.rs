@@ -0,0 +1,223 @@
+//! Counters and timings for finding out which rules dominate a run.
+//!
+//! The reducer records every match attempt and firing per rule in
+//! the `Profile` it is given, see `Expression::rewrite_once_profiled`.
+//! Time is not measured on wasm32, where `Instant` is unavailable.
+
+use std::fmt::Write;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use crate::expr::*;
+use crate::reduce::RewriteRules;
+use crate::symbol_table::SymbolTable;
+
+/// Measures the time from `start_if` to `elapsed`, if it was started.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Timer(Option<Instant>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Timer {
+
+    pub fn start_if(enabled: bool) -> Self {
+        Timer(enabled.then(Instant::now))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.0.map(|started| started.elapsed()).unwrap_or_default()
+    }
+
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct Timer;
+
+#[cfg(target_arch = "wasm32")]
+impl Timer {
+
+    pub fn start_if(_enabled: bool) -> Self {
+        Timer
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleStats {
+    /// How often the left side was matched against a term
+    pub attempts: usize,
+    /// How often it matched, including when another rule was applied instead
+    pub matches: usize,
+    /// How often the rule was applied
+    pub firings: usize,
+    pub match_time: Duration,
+    pub interpolate_time: Duration
+}
+
+impl RuleStats {
+
+    pub fn cost(&self) -> Duration {
+        self.match_time + self.interpolate_time
+    }
+
+}
+
+/// The sizes of the terms a query went through, counted in symbols and variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryStats {
+    pub start: Expression,
+    pub steps: usize,
+    pub start_size: usize,
+    pub max_size: usize,
+    pub final_size: usize
+}
+
+impl QueryStats {
+
+    pub fn new(start: &Expression) -> Self {
+        let size = start.size();
+        QueryStats { start: start.clone(), steps: 0, start_size: size, max_size: size, final_size: size }
+    }
+
+    /// Count a step that left a term of `size`.
+    pub fn step(&mut self, size: usize) {
+        self.steps += 1;
+        self.max_size = self.max_size.max(size);
+        self.final_size = size;
+    }
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// By the index of the rule
+    pub rules: Vec<RuleStats>,
+    pub queries: Vec<QueryStats>
+}
+
+impl Profile {
+
+    pub fn rule(&mut self, idx: usize) -> &mut RuleStats {
+        if self.rules.len() <= idx {
+            self.rules.resize(idx + 1, RuleStats::default());
+        }
+        &mut self.rules[idx]
+    }
+
+    pub fn attempt(&mut self, idx: usize, matched: bool, time: Duration) {
+        let stats = self.rule(idx);
+        stats.attempts += 1;
+        stats.matches += matched as usize;
+        stats.match_time += time;
+    }
+
+    pub fn fire(&mut self, idx: usize, time: Duration) {
+        let stats = self.rule(idx);
+        stats.firings += 1;
+        stats.interpolate_time += time;
+    }
+
+    /// A table of the rules that were tried, the most expensive first,
+    /// followed by the term sizes of each query.
+    pub fn report(&self, rules: &RewriteRules, symbols: &SymbolTable) -> String {
+        let mut order: Vec<usize> = (0..self.rules.len().min(rules.len()))
+            .filter(|idx| self.rules[*idx].attempts > 0)
+            .collect();
+        order.sort_by_key(|idx| {
+            let stats = &self.rules[*idx];
+            (std::cmp::Reverse(stats.cost()), std::cmp::Reverse(stats.attempts))
+        });
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{:>9} {:>9} {:>9} {:>10} {:>10}  rule", "attempts", "matches", "fired", "match ms", "interp ms");
+        for idx in order {
+            let stats = &self.rules[idx];
+            let rule = rules.get(idx);
+            let label = rule.label.map(|l| format!("[{}] ", symbols.lookup(l))).unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{:>9} {:>9} {:>9} {:>10.3} {:>10.3}  {}{} -> {}",
+                stats.attempts,
+                stats.matches,
+                stats.firings,
+                stats.match_time.as_secs_f64() * 1000.0,
+                stats.interpolate_time.as_secs_f64() * 1000.0,
+                label,
+                rule.left.bind(symbols),
+                rule.right.bind(symbols)
+            );
+        }
+        if !self.queries.is_empty() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "{:>9} {:>9} {:>9} {:>9}  query", "steps", "size", "max size", "final");
+            for query in &self.queries {
+                let _ = writeln!(
+                    out,
+                    "{:>9} {:>9} {:>9} {:>9}  {}",
+                    query.steps, query.start_size, query.max_size, query.final_size, query.start.bind(symbols)
+                );
+            }
+        }
+        out
+    }
+
+}
+
+impl Expression {
+
+    /// How many symbols, variables and other leaves make up this expression.
+    pub fn size(&self) -> usize {
+        self.0.iter().map(Terminal::size).sum()
+    }
+
+}
+
+impl Terminal {
+
+    pub fn size(&self) -> usize {
+        match self {
+            Terminal::Parentheses(e) => e.size(),
+            Terminal::Binder(_, body) => 1 + body.size(),
+            Terminal::Substitution(_, arg) => 1 + arg.size(),
+            _ => 1
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::reduce::{RewriteRule, Strategy};
+    use super::*;
+
+    #[test]
+    fn test_rule_counters() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        rules.add(RewriteRule::new(expr!(symbols [x] + 0), expr!(symbols [x])));
+        rules.add(RewriteRule::new(expr!(symbols [x] + (S [y])), expr!(symbols S ([x] + [y]))));
+        let mut profile = Profile::default();
+        let term = expr!(symbols a + (S (S 0)));
+        let derivation = term.derive_profiled(&rules, &symbols, 10, Strategy::Innermost, Some(&mut profile));
+        assert_eq!(derivation.steps.len(), 3);
+
+        assert_eq!(profile.rules[0].firings, 1);
+        assert_eq!(profile.rules[1].firings, 2);
+        assert_eq!(profile.rules[1].matches, 2);
+        assert!(profile.rules[0].attempts > profile.rules[0].matches);
+        let report = profile.report(&rules, &symbols);
+        assert!(report.contains("$x + (S $y) -> S ($x + $y)"));
+    }
+
+    #[test]
+    fn test_size() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(expr!(symbols S ((S 0) + [x])).size(), 5);
+    }
+
+}
@@ -0,0 +1,197 @@
+//! Checking calculational proofs.
+//!
+//! A proof is a chain of terms where each step cites the label of
+//! the rule that justifies it:
+//!
+//! ```text
+//! proof 1 + 0
+//! = 1      {by define-addition-0}
+//! = S 0    {by define-one}
+//! qed
+//! ```
+//!
+//! A step holds if the cited rule, applied once at some position
+//! in either direction, turns the previous term into the next one.
+
+use crate::assertion::diff;
+use crate::binder::body_expression;
+use crate::expr::*;
+use crate::interpolate::Interpolate;
+use crate::reduce::{format_position, Position, RewriteRule, RewriteRules};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// A term obtained by applying the cited rule once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub term: Expression,
+    pub position: Position,
+    /// True if the rule was applied from right to left
+    pub reversed: bool
+}
+
+/// Why a proof step does not follow from its cited rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepError {
+    /// No rule has the cited label
+    UnknownLabel,
+    /// The rule applies nowhere in the previous term
+    NotApplicable,
+    /// The rule applies, but never yields the next term.
+    /// The candidate differing from it in the fewest places is kept.
+    Mismatch(Candidate)
+}
+
+impl StepError {
+
+    pub fn describe(&self, label: SymbolHandle, symbols: &SymbolTable) -> String {
+        let label = symbols.lookup(label);
+        match self {
+            StepError::UnknownLabel => format!("no rule is labeled {}", label),
+            StepError::NotApplicable => format!("{} does not apply in either direction", label),
+            StepError::Mismatch(closest) => format!(
+                "closest: {} ({} {} at {})",
+                closest.term.bind(symbols),
+                label,
+                if closest.reversed { "backwards" } else { "forwards" },
+                format_position(&closest.position)
+            )
+        }
+    }
+
+}
+
+/// Every position in `expr`, outermost first.
+fn positions(expr: &Expression, prefix: &mut Position, out: &mut Vec<Position>) {
+    out.push(prefix.clone());
+    if expr.0.len() > 1 {
+        for (idx, term) in expr.0.iter().enumerate() {
+            prefix.push(idx);
+            match term {
+                Terminal::Parentheses(e) => positions(e, prefix, out),
+                _ => positions(&Expression(vec![term.clone()]), prefix, out)
+            }
+            prefix.pop();
+        }
+    } else if let [Terminal::Binder(_, body)] = expr.0.as_slice() {
+        prefix.push(0);
+        positions(&body_expression(body), prefix, out);
+        prefix.pop();
+    }
+}
+
+/// Every term reachable from `term` with one application of `rules`.
+/// Unlike reduction, a lone variable may match a whole compound subterm,
+/// so that a rule like `$x + 0 -> $x` can be applied backwards.
+fn candidates(term: &Expression, rules: &RewriteRules, symbols: &SymbolTable, reversed: bool) -> Vec<Candidate> {
+    let mut all = vec![];
+    positions(term, &mut vec![], &mut all);
+    let mut found = vec![];
+    for position in all {
+        let Some(subterm) = term.subterm(&position) else {
+            continue;
+        };
+        let mut views = vec![subterm.clone()];
+        if subterm.0.len() > 1 {
+            views.push(Expression(vec![Terminal::Parentheses(subterm)]));
+        }
+        for view in &views {
+            for (_, rule, bindings) in rules.find_matches(view, symbols) {
+                let mut next = term.clone();
+                if next.replace_at(&position, rule.right.interpolate(&bindings)) {
+                    found.push(Candidate { term: next, position: position.clone(), reversed });
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Check that `to` follows from `from` by the rule labeled `label`.
+pub fn check_step(from: &Expression, to: &Expression, label: SymbolHandle, rules: &RewriteRules, symbols: &SymbolTable) -> Result<Candidate, StepError> {
+    let mut forwards = RewriteRules::new();
+    let mut backwards = RewriteRules::new();
+    for rule in rules.iter().filter(|rule| rule.label == Some(label)) {
+        forwards.add(rule.clone());
+        let reversed = RewriteRule::labeled(rule.label, rule.right.clone(), rule.left.clone());
+        backwards.add(reversed.with_constraints(rule.constraints.clone()));
+    }
+    if forwards.is_empty() {
+        return Err(StepError::UnknownLabel);
+    }
+
+    let mut all = candidates(from, &forwards, symbols, false);
+    all.extend(candidates(from, &backwards, symbols, true));
+    if let Some(found) = all.iter().find(|c| c.term == *to) {
+        return Ok(found.clone());
+    }
+    all.into_iter()
+        .min_by_key(|c| diff(to, &c.term).len())
+        .map(|closest| Err(StepError::Mismatch(closest)))
+        .unwrap_or(Err(StepError::NotApplicable))
+}
+
+/// A proof being checked one step at a time.
+#[derive(Debug, Clone)]
+pub struct Proof {
+    pub start: Expression,
+    pub current: Expression,
+    /// Steps that did not follow from their cited rule
+    pub failures: usize
+}
+
+impl Proof {
+
+    pub fn new(start: Expression) -> Self {
+        Proof { current: start.clone(), start, failures: 0 }
+    }
+
+    /// Check the next step. The proof continues from `to` either way,
+    /// so that later steps are checked on their own.
+    pub fn step(&mut self, to: Expression, label: SymbolHandle, rules: &RewriteRules, symbols: &SymbolTable) -> Result<Candidate, StepError> {
+        let result = check_step(&self.current, &to, label, rules, symbols);
+        if result.is_err() {
+            self.failures += 1;
+        }
+        self.current = to;
+        result
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_check_step() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let add_0 = symbols.handle("define-addition-0");
+        rules.add(RewriteRule::labeled(Some(add_0), expr!(symbols [x] + 0), expr!(symbols [x])));
+
+        let step = check_step(&expr!(symbols S (a + 0)), &expr!(symbols S a), add_0, &rules, &symbols).unwrap();
+        assert_eq!(step.position, vec![1]);
+        assert!(!step.reversed);
+
+        // The same rule justifies the step in the other direction
+        let step = check_step(&expr!(symbols S a), &expr!(symbols S (a + 0)), add_0, &rules, &symbols).unwrap();
+        assert!(step.reversed);
+
+        let unknown = symbols.handle("unknown");
+        assert_eq!(check_step(&expr!(symbols a), &expr!(symbols b), unknown, &rules, &symbols), Err(StepError::UnknownLabel));
+    }
+
+    #[test]
+    fn test_closest_candidate() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let one = symbols.handle("define-one");
+        rules.add(RewriteRule::labeled(Some(one), expr!(symbols 1), expr!(symbols S 0)));
+        match check_step(&expr!(symbols 1 + 1), &expr!(symbols (S 0) + 2), one, &rules, &symbols) {
+            Err(StepError::Mismatch(closest)) => assert_eq!(closest.term, expr!(symbols (S 0) + 1)),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+}
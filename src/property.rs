@@ -0,0 +1,200 @@
+//! Checking `forall` properties on generated ground terms.
+//!
+//! The smallest terms of the signature are tried in every combination,
+//! then random ones. The first counterexample is shrunk by replacing
+//! its terms with smaller ones for as long as the property still fails.
+
+use crate::assertion::{self, Failure};
+use crate::expr::*;
+use crate::interpolate::Interpolate;
+use crate::matches::VariableBinding;
+use crate::reduce::{RewriteRules, Strategy};
+use crate::signature::{Rng, Signature};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// How many assignments of terms to variables are tried
+pub const MAX_CASES: usize = 200;
+
+/// Generated terms have at most this many symbols
+pub const MAX_TERM_SIZE: usize = 6;
+
+/// Each instance gives up after this many steps unless a step limit was set
+pub const MAX_STEPS: usize = 1000;
+
+/// Terms for which a property does not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    pub bindings: Vec<(SymbolHandle, Terminal)>,
+    pub failure: Failure
+}
+
+impl Counterexample {
+
+    /// A human readable explanation, one line per entry.
+    pub fn describe(&self, symbols: &SymbolTable) -> Vec<String> {
+        let bindings: Vec<String> = self.bindings.iter()
+            .map(|(var, term)| {
+                let term = Expression(vec![term.clone()]).without_redundant_parens();
+                format!("${} = {}", symbols.lookup(*var), term.bind(symbols))
+            })
+            .collect();
+        let mut lines = vec![format!("counterexample: {}", bindings.join(", "))];
+        lines.extend(self.failure.describe(symbols));
+        lines
+    }
+
+}
+
+/// Checks properties against a rule set.
+pub struct Checker<'a> {
+    rules: &'a RewriteRules,
+    symbols: &'a SymbolTable,
+    signature: &'a Signature,
+    strategy: Strategy,
+    max_steps: usize,
+    pub max_size: usize,
+    pub max_cases: usize,
+    /// Seeds the random terms tried after the smallest ones
+    pub seed: u64
+}
+
+impl<'a> Checker<'a> {
+
+    pub fn new(rules: &'a RewriteRules, symbols: &'a SymbolTable, signature: &'a Signature, strategy: Strategy, max_steps: usize) -> Self {
+        Checker { rules, symbols, signature, strategy, max_steps, max_size: MAX_TERM_SIZE, max_cases: MAX_CASES, seed: 0 }
+    }
+
+    /// Check the assertion `body` for ground terms in place of `vars`,
+    /// returning how many cases were tried or the smallest counterexample found.
+    pub fn check(&self, vars: &[SymbolHandle], body: &Statement) -> Result<usize, Counterexample> {
+        let cases = self.cases(vars.len());
+        for case in &cases {
+            if let Err(failure) = self.check_case(vars, body, case) {
+                let (terms, failure) = self.shrink(vars, body, case.clone(), failure);
+                return Err(Counterexample { bindings: vars.iter().copied().zip(terms).collect(), failure });
+            }
+        }
+        Ok(cases.len())
+    }
+
+    fn cases(&self, arity: usize) -> Vec<Vec<Terminal>> {
+        let terms = self.signature.enumerate(self.max_size, self.max_cases);
+        let fits = |n: usize| n.checked_pow(arity as u32).is_some_and(|cases| cases <= self.max_cases);
+        let exhaustive = fits(terms.len());
+        // Otherwise the smallest terms take up half the cases and random ones the rest
+        let smallest = if exhaustive {
+            terms.len()
+        } else {
+            (1..=terms.len()).take_while(|k| k.checked_pow(arity as u32).is_some_and(|n| n <= self.max_cases / 2)).last().unwrap_or(0)
+        };
+        let mut cases = vec![vec![]];
+        for _ in 0..arity {
+            cases = cases.into_iter()
+                .flat_map(|case: Vec<Terminal>| terms[..smallest].iter().map(move |t| [case.clone(), vec![t.clone()]].concat()))
+                .collect();
+        }
+        cases.sort_by_key(|case| case.iter().map(Terminal::size).sum::<usize>());
+        if !exhaustive {
+            let mut rng = Rng::new(self.seed);
+            while cases.len() < self.max_cases {
+                let case: Option<Vec<Terminal>> = (0..arity).map(|_| self.signature.sample(&mut rng, self.max_size)).collect();
+                match case {
+                    Some(case) => cases.push(case),
+                    None => break
+                }
+            }
+        }
+        cases
+    }
+
+    fn check_case(&self, vars: &[SymbolHandle], body: &Statement, terms: &[Terminal]) -> Result<(), Failure> {
+        let bindings: Vec<VariableBinding> = vars.iter().zip(terms)
+            .map(|(var, expr)| VariableBinding { var: *var, expr, kind: VariableKind::Any, depth: 0 })
+            .collect();
+        let instance = match body {
+            Statement::Reaches(l, r) => Statement::Reaches(l.interpolate(&bindings), r.interpolate(&bindings)),
+            Statement::NotReaches(l, r) => Statement::NotReaches(l.interpolate(&bindings), r.interpolate(&bindings)),
+            Statement::Expect(l, r) => Statement::Expect(l.interpolate(&bindings), r.interpolate(&bindings)),
+            other => other.clone()
+        };
+        assertion::check(&instance, self.rules, self.symbols, self.strategy, self.max_steps, None)
+    }
+
+    fn shrink(&self, vars: &[SymbolHandle], body: &Statement, mut terms: Vec<Terminal>, mut failure: Failure) -> (Vec<Terminal>, Failure) {
+        'smaller: loop {
+            for idx in 0..terms.len() {
+                for candidate in self.smaller(&terms[idx]) {
+                    let mut attempt = terms.clone();
+                    attempt[idx] = candidate;
+                    if let Err(f) = self.check_case(vars, body, &attempt) {
+                        terms = attempt;
+                        failure = f;
+                        continue 'smaller;
+                    }
+                }
+            }
+            return (terms, failure);
+        }
+    }
+
+    /// Terms with fewer symbols than `term` to try in its place: its
+    /// arguments, the constants, and `term` with a smaller argument.
+    fn smaller(&self, term: &Terminal) -> Vec<Terminal> {
+        let Terminal::Parentheses(e) = term else {
+            return vec![];
+        };
+        let is_argument = |t: &&Terminal| match t {
+            Terminal::Parentheses(_) => true,
+            Terminal::Symbol(s) => self.signature.constants.contains(s),
+            _ => false
+        };
+        let mut out: Vec<Terminal> = e.0.iter().filter(is_argument).cloned().collect();
+        out.extend(self.signature.constants.iter().map(|c| Terminal::Symbol(*c)));
+        for (idx, child) in e.0.iter().enumerate() {
+            for candidate in self.smaller(child) {
+                let mut terms = e.0.clone();
+                terms[idx] = candidate;
+                out.push(Terminal::Parentheses(Expression(terms)));
+            }
+        }
+        out
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::parse::TryParse;
+    use crate::trs::Trs;
+    use super::*;
+
+    fn check(src: &str, signature: &str, property: &str) -> Result<usize, Vec<String>> {
+        let (mut symbols, rules) = Trs::from_source(src).unwrap().into_parts();
+        let Ok(Statement::Signature(signature)) = Statement::parse_complete(signature, &mut symbols) else {
+            panic!("not a signature");
+        };
+        let Ok(Statement::Property(vars, body)) = Statement::parse_complete(property, &mut symbols) else {
+            panic!("not a property");
+        };
+        Checker::new(&rules, &symbols, &signature, Strategy::default(), MAX_STEPS)
+            .check(&vars, &body)
+            .map_err(|counterexample| counterexample.describe(&symbols))
+    }
+
+    #[test]
+    fn test_property_holds() {
+        let src = "$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)\n$x = $x -> true";
+        let cases = check(src, "signature 0, S _, _ + _", "forall $x $y: ($x + $y) = ($y + $x) ->* true").unwrap();
+        assert_eq!(cases, MAX_CASES);
+    }
+
+    #[test]
+    fn test_shrink() {
+        // Wrong for any $y other than 0
+        let src = "$x + 0 -> $x\n$x + (S $y) -> $x + $y\n$x = $x -> true";
+        let lines = check(src, "signature 0, S _", "forall $x $y: ($x + $y) = ($y + $x) ->* true").unwrap_err();
+        assert_eq!(lines[0], "counterexample: $x = 0, $y = S 0");
+    }
+
+}
@@ -1,43 +1,268 @@
+use std::str::FromStr;
+
+use crate::binder::{body_expression, expression_body, refers_to, shallowest_use};
 use crate::expr::*;
 use crate::matches::*;
 use crate::interpolate::*;
+use crate::profile::{Profile, Timer};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
 
+#[derive(Default, Clone)]
 pub struct RewriteRules {
-    rules: Vec<RewriteRule>
+    rules: Vec<RewriteRule>,
+    // Declared with `:lazy`, where reduction may not look inside
+    lazy: Vec<ReplacementMap>
 }
 
-impl<'s> RewriteRules {
+impl RewriteRules {
 
     pub fn new() -> Self {
-        RewriteRules { rules: vec![] }
+        RewriteRules { rules: vec![], lazy: vec![] }
     }
 
     pub fn add(&mut self, rule: RewriteRule) {
         self.rules.push(rule)
     }
 
-    pub fn find_matches<'t>(&self, expr: &'t Expression) -> Vec<(&RewriteRule, Vec<VariableBinding<'t>>)> {
+    pub fn add_lazy(&mut self, map: ReplacementMap) {
+        self.lazy.push(map)
+    }
+
+    pub fn lazy(&self) -> impl Iterator<Item = &ReplacementMap> {
+        self.lazy.iter()
+    }
+
+    /// True if the term at `idx` of `expr` must not be rewritten.
+    pub fn is_frozen(&self, expr: &Expression, idx: usize) -> bool {
+        self.lazy.iter().any(|map| map.freezes(expr, idx))
+    }
+
+    /// Remove every rule with the given label, returning how many were removed.
+    pub fn remove_labeled(&mut self, label: SymbolHandle) -> usize {
+        let before = self.rules.len();
+        self.rules.retain(|rule| rule.label != Some(label));
+        before - self.rules.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+        self.lazy.clear();
+    }
+
+    pub fn get(&self, idx: usize) -> &RewriteRule {
+        &self.rules[idx]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RewriteRule> {
+        self.rules.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Every rule whose left side matches `expr`, along with
+    /// the index of the rule and the variable bindings of the match.
+    pub fn find_matches<'t>(&self, expr: &'t Expression, symbols: &SymbolTable) -> Vec<(usize, &RewriteRule, Vec<VariableBinding<'t>>)> {
+        self.find_matches_profiled(expr, symbols, None)
+    }
+
+    /// Like `find_matches`, counting every attempt in `profile`.
+    fn find_matches_profiled<'t>(&self, expr: &'t Expression, symbols: &SymbolTable, mut profile: Option<&mut Profile>) -> Vec<(usize, &RewriteRule, Vec<VariableBinding<'t>>)> {
         let mut matches = vec![];
-        for rule in &self.rules {
-            let mut bindings = vec![];
-            if rule.left.matches(expr, &mut bindings) {
-                matches.push((rule, bindings))
+        for (idx, rule) in self.rules.iter().enumerate() {
+            let timer = Timer::start_if(profile.is_some());
+            let bindings = rule.bindings(expr, symbols);
+            if let Some(profile) = profile.as_deref_mut() {
+                profile.attempt(idx, bindings.is_some(), timer.elapsed());
+            }
+            if let Some(bindings) = bindings {
+                matches.push((idx, rule, bindings))
             }
         }
         matches
     }
 
+    /// Every place in `expr` where some rule applies, in the order
+    /// `Expression::rewrite_once` considers them: innermost and leftmost
+    /// first, then the expression as a whole, trying rules in order.
+    pub fn find_redexes(&self, expr: &Expression, symbols: &SymbolTable) -> Vec<Rewrite> {
+        let mut redexes = vec![];
+        self.collect_redexes(expr, symbols, &mut vec![], &mut redexes);
+        redexes
+    }
+
+    fn collect_redexes(&self, expr: &Expression, symbols: &SymbolTable, position: &mut Position, redexes: &mut Vec<Rewrite>) {
+        if expr.0.len() > 1 {
+            for (idx, term) in expr.0.iter().enumerate() {
+                if self.is_frozen(expr, idx) {
+                    continue;
+                }
+                position.push(idx);
+                match term {
+                    Terminal::Parentheses(e) => self.collect_redexes(e, symbols, position, redexes),
+                    _ => self.collect_redexes(&Expression(vec![term.clone()]), symbols, position, redexes)
+                }
+                position.pop();
+            }
+        } else if let [Terminal::Binder(_, body)] = expr.0.as_slice() {
+            position.push(0);
+            self.collect_redexes(&body_expression(body), symbols, position, redexes);
+            position.pop();
+        }
+        for (rule, _, _) in self.find_matches(expr, symbols) {
+            redexes.push(Rewrite { rule, position: position.clone() });
+        }
+    }
+
+}
+
+/// A part of a `:lazy` declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Symbol(SymbolHandle),
+    /// `!`, an argument that may be rewritten
+    Active,
+    /// `_`, an argument that is left as it is
+    Frozen
 }
 
+/// Which arguments of terms shaped like `if ! then _ else _` may be
+/// rewritten, so that only the branch that is taken gets evaluated.
+/// Without any `!`, only the first argument is active.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementMap(pub Vec<Slot>);
+
+impl ReplacementMap {
+
+    /// True if `expr` has this shape and its term at `idx` is frozen.
+    pub fn freezes(&self, expr: &Expression, idx: usize) -> bool {
+        if expr.0.len() != self.0.len() || self.0[idx] != Slot::Frozen {
+            return false;
+        }
+        let shaped = self.0.iter().zip(&expr.0).all(|(slot, term)| match slot {
+            Slot::Symbol(s) => *term == Terminal::Symbol(*s),
+            _ => true
+        });
+        let first = self.0.iter().position(|slot| *slot == Slot::Frozen);
+        shaped && (self.0.contains(&Slot::Active) || first != Some(idx))
+    }
+
+}
+
+#[derive(Clone)]
 pub struct RewriteRule {
+    pub label: Option<SymbolHandle>,
     pub left: Expression,
-    pub right: Expression
+    pub right: Expression,
+    pub constraints: Vec<Constraint>
 }
 
 impl RewriteRule {
 
     pub fn new(left: Expression, right: Expression) -> Self {
-        RewriteRule { left, right }
+        RewriteRule { label: None, left, right, constraints: vec![] }
+    }
+
+    pub fn labeled(label: Option<SymbolHandle>, left: Expression, right: Expression) -> Self {
+        RewriteRule { label, left, right, constraints: vec![] }
+    }
+
+    pub fn with_constraints(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    /// The bindings of the left side matched against `expr`, if it matches,
+    /// the constraints hold and no term is moved out of a binder it refers to.
+    pub fn bindings<'t>(&self, expr: &'t Expression, symbols: &SymbolTable) -> Option<Vec<VariableBinding<'t>>> {
+        let mut bindings = vec![];
+        if !self.left.matches(expr, &mut bindings, symbols) {
+            return None;
+        }
+        // A right side under fewer binders than the match would leave these indices dangling
+        let escapes = bindings.iter().any(|b| match shallowest_use(&self.right.0, b.var) {
+            Some(depth) if depth < b.depth => refers_to(b.expr, depth..b.depth),
+            _ => false
+        });
+        (!escapes && self.constraints.iter().all(|c| c.holds(&bindings, symbols)))
+            .then_some(bindings)
+    }
+
+}
+
+/// Which redex to rewrite when several rules apply at different positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Leftmost innermost: arguments are reduced before the terms containing them
+    #[default]
+    Innermost,
+    /// Leftmost outermost: terms are rewritten before their arguments
+    Outermost
+}
+
+impl FromStr for Strategy {
+
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "innermost" => Ok(Strategy::Innermost),
+            "outermost" => Ok(Strategy::Outermost),
+            _ => Err(format!("Unknown strategy \"{}\", expected innermost or outermost", s))
+        }
+    }
+
+}
+
+/// Where in an expression a rewrite happened.
+/// Each index selects a terminal of the expression at that depth,
+/// descending into `Parentheses`. The empty position is the whole expression.
+pub type Position = Vec<usize>;
+
+/// Positions are written as dotted paths, `ε` is the whole term.
+pub fn format_position(position: &[usize]) -> String {
+    if position.is_empty() {
+        return "ε".to_owned();
+    }
+    position.iter().map(|idx| idx.to_string()).collect::<Vec<_>>().join(".")
+}
+
+/// A single rewrite applied by `Expression::rewrite_once`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    /// Index of the applied rule in its `RewriteRules`
+    pub rule: usize,
+    pub position: Position
+}
+
+/// The sequence of terms visited while normalizing an expression.
+#[derive(Debug, Clone)]
+pub struct Derivation {
+    pub start: Expression,
+    pub steps: Vec<DerivationStep>,
+    /// True if the derivation stopped at the step limit
+    /// rather than at a normal form.
+    pub truncated: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct DerivationStep {
+    pub rule: usize,
+    pub label: Option<SymbolHandle>,
+    pub position: Position,
+    pub term: Expression
+}
+
+impl Derivation {
+
+    /// The last term of the derivation.
+    pub fn result(&self) -> &Expression {
+        self.steps.last().map(|s| &s.term).unwrap_or(&self.start)
     }
 
 }
@@ -47,65 +272,209 @@ impl Expression {
     /// Apply a single reduction step to this expression.
     /// Returns true if a rewrite was applied and false
     /// if no rewrites matched.
-    pub fn reduce_once(&mut self, rules: &RewriteRules) -> bool {
+    pub fn reduce_once(&mut self, rules: &RewriteRules, symbols: &SymbolTable) -> bool {
+        self.rewrite_once(rules, symbols).is_some()
+    }
 
-        // TODO: Can this be rewritten to use an explicit stack instead of recursion?
+    /// Reduce a copy of this expression until it reaches a normal form
+    /// or `max_steps` rewrites have been applied, recording each step.
+    pub fn derive(&self, rules: &RewriteRules, symbols: &SymbolTable, max_steps: usize) -> Derivation {
+        self.derive_with(rules, symbols, max_steps, Strategy::Innermost)
+    }
 
-        // For complex expressions with many subexpressions,
-        // we need to apply rewrite rules to all subexpressions
-        // before rewriting this expression. This means parentheses
-        // but also each individual term evaluated as a single-term expression.
-        if self.0.len() > 1 {
-            let mut swap = None;
-            for (idx, term) in &mut self.0.iter_mut().enumerate() {
-                match term {
-                    Terminal::Parentheses(e) => {
-                        if e.reduce_once(rules) {
-                            swap = Some((idx, e.clone()));
-                            break;
-                        }
-                    },
-                    Terminal::Symbol(s) => {
-                        let mut expr = Expression(vec![Terminal::Symbol(*s)]);
-                        if expr.reduce_once(rules) {
-                            swap = Some((idx, expr));
-                            break;
-                        }
-                    },
-                    Terminal::Variable(v, k) => {
-                        let mut expr = Expression(vec![Terminal::Variable(*v, *k)]);
-                        if expr.reduce_once(rules) {
-                            swap = Some((idx, expr));
-                            break;
-                        }
+    pub fn derive_with(&self, rules: &RewriteRules, symbols: &SymbolTable, max_steps: usize, strategy: Strategy) -> Derivation {
+        self.derive_profiled(rules, symbols, max_steps, strategy, None)
+    }
+
+    /// Like `derive_with`, counting match attempts and firings in `profile`.
+    pub fn derive_profiled(&self, rules: &RewriteRules, symbols: &SymbolTable, max_steps: usize, strategy: Strategy, mut profile: Option<&mut Profile>) -> Derivation {
+        let mut term = self.clone();
+        let mut steps = vec![];
+        while steps.len() < max_steps {
+            match term.rewrite_once_profiled(rules, symbols, strategy, profile.as_deref_mut()) {
+                Some(rewrite) => steps.push(DerivationStep {
+                    rule: rewrite.rule,
+                    label: rules.get(rewrite.rule).label,
+                    position: rewrite.position,
+                    term: term.clone()
+                }),
+                None => return Derivation { start: self.clone(), steps, truncated: false }
+            }
+        }
+        let truncated = term.clone().rewrite_once_with(rules, symbols, strategy).is_some();
+        Derivation { start: self.clone(), steps, truncated }
+    }
+
+    /// The subexpression at `position`. A position that selects a single
+    /// symbol or variable yields a one-term expression.
+    /// The body of a binder is its only child, at index 0.
+    pub fn subterm(&self, position: &[usize]) -> Option<Expression> {
+        let (idx, rest) = match position.split_first() {
+            None => return Some(self.clone()),
+            Some(split) => split
+        };
+        if let [Terminal::Binder(_, body)] = self.0.as_slice() {
+            return if *idx == 0 { body_expression(body).subterm(rest) } else { None };
+        }
+        match self.0.get(*idx)? {
+            Terminal::Parentheses(e) => e.subterm(rest),
+            term if rest.is_empty() => Some(Expression(vec![term.clone()])),
+            term @ Terminal::Binder(..) => Expression(vec![term.clone()]).subterm(rest),
+            _ => None
+        }
+    }
+
+    /// Replace the subexpression at `position`, unwrapping the
+    /// replacement if it consists of a single term.
+    /// Returns false if `position` does not exist in this expression.
+    pub fn replace_at(&mut self, position: &[usize], mut replacement: Expression) -> bool {
+        let Some((idx, rest)) = position.split_first() else {
+            *self = replacement;
+            return true;
+        };
+        if let [Terminal::Binder(_, body)] = self.0.as_mut_slice() {
+            let mut inner = body_expression(body);
+            if *idx != 0 || !inner.replace_at(rest, replacement) {
+                return false;
+            }
+            **body = expression_body(inner);
+            return true;
+        }
+        let Some(term) = self.0.get_mut(*idx) else {
+            return false;
+        };
+        if !rest.is_empty() {
+            return match term {
+                Terminal::Parentheses(e) => {
+                    if !e.replace_at(rest, replacement) {
+                        return false;
                     }
-                }
+                    if e.0.len() == 1 {
+                        *term = e.0.pop().unwrap();
+                    }
+                    true
+                },
+                Terminal::Binder(..) => {
+                    let mut wrapped = Expression(vec![term.clone()]);
+                    if !wrapped.replace_at(rest, replacement) {
+                        return false;
+                    }
+                    *term = wrapped.0.pop().unwrap();
+                    true
+                },
+                _ => false
+            };
+        }
+        *term = if replacement.0.len() == 1 {
+            replacement.0.pop().unwrap()
+        } else {
+            Terminal::Parentheses(replacement)
+        };
+        true
+    }
+
+    /// Apply a rewrite found by `RewriteRules::find_redexes`.
+    /// Returns false if the rule does not match at that position.
+    pub fn apply(&mut self, rewrite: &Rewrite, rules: &RewriteRules, symbols: &SymbolTable) -> bool {
+        let Some(subterm) = self.subterm(&rewrite.position) else {
+            return false;
+        };
+        let rule = rules.get(rewrite.rule);
+        let Some(bindings) = rule.bindings(&subterm, symbols) else {
+            return false;
+        };
+        self.replace_at(&rewrite.position, rule.right.interpolate(&bindings))
+    }
+
+    /// Like `reduce_once`, but reports which rule was applied and where.
+    pub fn rewrite_once(&mut self, rules: &RewriteRules, symbols: &SymbolTable) -> Option<Rewrite> {
+        self.rewrite_once_with(rules, symbols, Strategy::Innermost)
+    }
+
+    /// Apply a single reduction step, choosing the redex by `strategy`.
+    pub fn rewrite_once_with(&mut self, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy) -> Option<Rewrite> {
+        self.rewrite_once_profiled(rules, symbols, strategy, None)
+    }
+
+    /// Like `rewrite_once_with`, counting match attempts and firings in `profile`.
+    pub fn rewrite_once_profiled(&mut self, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, mut profile: Option<&mut Profile>) -> Option<Rewrite> {
+
+        // TODO: Can this be rewritten to use an explicit stack instead of recursion?
+
+        match strategy {
+            // For complex expressions with many subexpressions,
+            // we need to apply rewrite rules to all subexpressions
+            // before rewriting this expression.
+            Strategy::Innermost => self.rewrite_children(rules, symbols, strategy, profile.as_deref_mut())
+                .or_else(|| self.rewrite_root(rules, symbols, profile)),
+            Strategy::Outermost => self.rewrite_root(rules, symbols, profile.as_deref_mut())
+                .or_else(|| self.rewrite_children(rules, symbols, strategy, profile))
+        }
+    }
+
+    /// Rewrite the first subexpression that can be rewritten.
+    /// This means parentheses but also each individual term
+    /// evaluated as a single-term expression, and the body of a binder.
+    fn rewrite_children(&mut self, rules: &RewriteRules, symbols: &SymbolTable, strategy: Strategy, mut profile: Option<&mut Profile>) -> Option<Rewrite> {
+        if let [Terminal::Binder(_, body)] = self.0.as_mut_slice() {
+            let mut inner = body_expression(body);
+            let mut rewrite = inner.rewrite_once_profiled(rules, symbols, strategy, profile)?;
+            **body = expression_body(inner);
+            rewrite.position.insert(0, 0);
+            return Some(rewrite);
+        }
+        if self.0.len() <= 1 {
+            return None;
+        }
+        let frozen: Vec<bool> = (0..self.0.len()).map(|idx| rules.is_frozen(self, idx)).collect();
+        let mut swap = None;
+        for (idx, term) in &mut self.0.iter_mut().enumerate() {
+            if frozen[idx] {
+                continue;
             }
-            if let Some((idx, mut expr)) = swap {
-                if expr.0.len() == 1 {
-                    self.0[idx] = expr.0.pop().unwrap();
-                } else {
-                    self.0[idx] = Terminal::Parentheses(expr);
+            match term {
+                Terminal::Parentheses(e) => {
+                    if let Some(rewrite) = e.rewrite_once_profiled(rules, symbols, strategy, profile.as_deref_mut()) {
+                        swap = Some((idx, e.clone(), rewrite));
+                        break;
+                    }
+                },
+                _ => {
+                    let mut expr = Expression(vec![term.clone()]);
+                    if let Some(rewrite) = expr.rewrite_once_profiled(rules, symbols, strategy, profile.as_deref_mut()) {
+                        swap = Some((idx, expr, rewrite));
+                        break;
+                    }
                 }
-                return true;
             }
         }
-        
-        // If no subexpressions were simplified, we can apply
-        // the rewrite rules to this expression.
-        let matches = rules.find_matches(self);
-        if matches.is_empty() {
-            return false;
+        let (idx, mut expr, mut rewrite) = swap?;
+        if expr.0.len() == 1 {
+            self.0[idx] = expr.0.pop().unwrap();
+        } else {
+            self.0[idx] = Terminal::Parentheses(expr);
         }
+        rewrite.position.insert(0, idx);
+        Some(rewrite)
+    }
+
+    /// Apply the rewrite rules to this expression as a whole.
+    fn rewrite_root(&mut self, rules: &RewriteRules, symbols: &SymbolTable, mut profile: Option<&mut Profile>) -> Option<Rewrite> {
+        let matches = rules.find_matches_profiled(self, symbols, profile.as_deref_mut());
 
         // TODO: For now we just choose the first one but we should
         // have some form of explicit precedence when multiple rules
         // might apply. Or apply all rules and branch ... ?
-        let (rule, bindings) = &matches[0];
+        let (idx, rule, bindings) = matches.first()?;
+        let timer = Timer::start_if(profile.is_some());
         let rewritten = rule.right.interpolate(bindings);
+        if let Some(profile) = profile {
+            profile.fire(*idx, timer.elapsed());
+        }
+        let rule = *idx;
         self.0 = rewritten.0;
 
-        true
+        Some(Rewrite { rule, position: vec![] })
     }
 
 }
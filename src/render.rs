@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use crate::binder::{body_expression, open};
+use crate::expr::*;
+use crate::reduce::{Derivation, RewriteRule, RewriteRules};
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// Custom notation for individual symbols, for example
+/// `S` as `\mathsf{S}` in LaTeX or `*` as `<mo>&#x22C5;</mo>` in MathML.
+/// The notation is emitted verbatim in place of the symbol.
+#[derive(Debug, Clone, Default)]
+pub struct Notation {
+    symbols: HashMap<SymbolHandle, String>
+}
+
+impl Notation {
+
+    pub fn new() -> Self {
+        Notation { symbols: HashMap::new() }
+    }
+
+    pub fn set(&mut self, symbol: SymbolHandle, notation: impl Into<String>) {
+        self.symbols.insert(symbol, notation.into());
+    }
+
+    pub fn get(&self, symbol: SymbolHandle) -> Option<&str> {
+        self.symbols.get(&symbol).map(|s| s.as_str())
+    }
+
+}
+
+fn is_word(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '\'')
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Terms that read like words need explicit space between them
+/// when juxtaposed, operators are spaced by the renderer itself.
+fn is_wordlike(term: &Terminal, symbols: &SymbolTable) -> bool {
+    match term {
+        Terminal::Symbol(s) => is_word(symbols.lookup(*s)),
+        _ => true
+    }
+}
+
+/// A binder extends to the end of its expression, so it needs
+/// parentheses unless it is the last term.
+fn is_enclosed_binder(expr: &Expression, idx: usize) -> bool {
+    matches!(expr.0[idx], Terminal::Binder(..)) && idx + 1 < expr.0.len()
+}
+
+/// The body of a binder with its variable written as the name it was given.
+fn named_body(hint: Hint, body: &Terminal) -> Expression {
+    body_expression(&open(body, &Terminal::Symbol(hint.0)))
+}
+
+/// Renders terms, rules and derivations as LaTeX math.
+/// Derivations and rule sets use the `align*` environment from `amsmath`.
+pub struct Latex<'s> {
+    symbols: &'s SymbolTable,
+    notation: Notation
+}
+
+impl<'s> Latex<'s> {
+
+    pub fn new(symbols: &'s SymbolTable) -> Self {
+        Latex { symbols, notation: Notation::new() }
+    }
+
+    pub fn with_notation(symbols: &'s SymbolTable, notation: Notation) -> Self {
+        Latex { symbols, notation }
+    }
+
+    pub fn expression(&self, expr: &Expression) -> String {
+        let mut out = String::new();
+        for (idx, term) in expr.0.iter().enumerate() {
+            if idx > 0 {
+                let prev = &expr.0[idx - 1];
+                if is_wordlike(prev, self.symbols) && is_wordlike(term, self.symbols) {
+                    out.push_str(" \\; ");
+                } else {
+                    out.push(' ');
+                }
+            }
+            if is_enclosed_binder(expr, idx) {
+                out.push_str(&format!("\\left( {} \\right)", self.terminal(term)));
+            } else {
+                out.push_str(&self.terminal(term));
+            }
+        }
+        out
+    }
+
+    fn terminal(&self, term: &Terminal) -> String {
+        match term {
+            Terminal::Parentheses(e) => format!("\\left( {} \\right)", self.expression(e)),
+            Terminal::Variable(v, ..) => format!("\\mathit{{{}}}", latex_escape(self.symbols.lookup(*v))),
+            Terminal::Symbol(s) => {
+                if let Some(notation) = self.notation.get(*s) {
+                    return notation.to_owned();
+                }
+                let name = self.symbols.lookup(*s);
+                if is_number(name) {
+                    name.to_owned()
+                } else if is_word(name) {
+                    format!("\\mathrm{{{}}}", latex_escape(name))
+                } else {
+                    latex_escape(name)
+                }
+            },
+            Terminal::Binder(hint, body) => format!(
+                "\\lambda \\mathit{{{}}}.\\, {}",
+                latex_escape(self.symbols.lookup(hint.0)),
+                self.expression(&named_body(*hint, body))
+            ),
+            Terminal::Bound(idx) => format!("\\#{}", idx),
+            Terminal::Substitution(v, arg) => format!(
+                "\\mathit{{{}}}[{}]",
+                latex_escape(self.symbols.lookup(*v)),
+                self.terminal(arg)
+            )
+        }
+    }
+
+    fn arrow(&self, label: Option<SymbolHandle>) -> String {
+        match label {
+            Some(label) => format!("\\xrightarrow{{\\text{{{}}}}}", text_escape(self.symbols.lookup(label))),
+            None => "\\to".to_owned()
+        }
+    }
+
+    pub fn rule(&self, rule: &RewriteRule) -> String {
+        format!("{} {} {}", self.expression(&rule.left), self.arrow(rule.label), self.expression(&rule.right))
+    }
+
+    pub fn rules(&self, rules: &RewriteRules) -> String {
+        let rows: Vec<String> = rules.iter()
+            .map(|rule| {
+                let mut row = format!("{} &\\to {}", self.expression(&rule.left), self.expression(&rule.right));
+                if let Some(label) = rule.label {
+                    row.push_str(&format!(" && \\text{{{}}}", text_escape(self.symbols.lookup(label))));
+                }
+                row
+            })
+            .collect();
+        format!("\\begin{{align*}}\n{}\n\\end{{align*}}", rows.join(" \\\\\n"))
+    }
+
+    pub fn derivation(&self, derivation: &Derivation) -> String {
+        let mut rows = vec![];
+        for (idx, step) in derivation.steps.iter().enumerate() {
+            let start = if idx == 0 { format!("{} ", self.expression(&derivation.start)) } else { String::new() };
+            rows.push(format!("{}&{} {}", start, self.arrow(step.label), self.expression(&step.term)));
+        }
+        if rows.is_empty() {
+            rows.push(self.expression(&derivation.start));
+        }
+        format!("\\begin{{align*}}\n{}\n\\end{{align*}}", rows.join(" \\\\\n"))
+    }
+
+}
+
+fn latex_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\backslash "),
+            '{' | '}' | '#' | '$' | '%' | '&' | '_' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '^' => out.push_str("\\hat{}"),
+            '~' => out.push_str("\\sim "),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+/// Like `latex_escape`, for labels inside `\text{...}`, where math mode
+/// commands like `\backslash` are not allowed.
+fn text_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '#' | '$' | '%' | '&' | '_' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '^' => out.push_str("\\textasciicircum{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+/// Renders terms, rules and derivations as presentation MathML.
+pub struct MathMl<'s> {
+    symbols: &'s SymbolTable,
+    notation: Notation
+}
+
+impl<'s> MathMl<'s> {
+
+    pub fn new(symbols: &'s SymbolTable) -> Self {
+        MathMl { symbols, notation: Notation::new() }
+    }
+
+    pub fn with_notation(symbols: &'s SymbolTable, notation: Notation) -> Self {
+        MathMl { symbols, notation }
+    }
+
+    pub fn expression(&self, expr: &Expression) -> String {
+        let terms: String = expr.0.iter()
+            .enumerate()
+            .map(|(idx, t)| if is_enclosed_binder(expr, idx) {
+                format!("<mrow><mo>(</mo>{}<mo>)</mo></mrow>", self.terminal(t))
+            } else {
+                self.terminal(t)
+            })
+            .collect();
+        format!("<mrow>{}</mrow>", terms)
+    }
+
+    fn terminal(&self, term: &Terminal) -> String {
+        match term {
+            Terminal::Parentheses(e) => format!("<mrow><mo>(</mo>{}<mo>)</mo></mrow>", self.expression(e)),
+            Terminal::Variable(v, ..) => format!("<mi mathvariant=\"italic\">{}</mi>", xml_escape(self.symbols.lookup(*v))),
+            Terminal::Symbol(s) => {
+                if let Some(notation) = self.notation.get(*s) {
+                    return notation.to_owned();
+                }
+                let name = self.symbols.lookup(*s);
+                if is_number(name) {
+                    format!("<mn>{}</mn>", name)
+                } else if is_word(name) {
+                    format!("<mi mathvariant=\"normal\">{}</mi>", xml_escape(name))
+                } else {
+                    format!("<mo>{}</mo>", xml_escape(name))
+                }
+            },
+            Terminal::Binder(hint, body) => format!(
+                "<mrow><mi>&#x3BB;</mi><mi mathvariant=\"italic\">{}</mi><mo>.</mo>{}</mrow>",
+                xml_escape(self.symbols.lookup(hint.0)),
+                self.expression(&named_body(*hint, body))
+            ),
+            Terminal::Bound(idx) => format!("<mi>#{}</mi>", idx),
+            Terminal::Substitution(v, arg) => format!(
+                "<mrow><mi mathvariant=\"italic\">{}</mi><mo>[</mo>{}<mo>]</mo></mrow>",
+                xml_escape(self.symbols.lookup(*v)),
+                self.terminal(arg)
+            )
+        }
+    }
+
+    fn arrow(&self, label: Option<SymbolHandle>) -> String {
+        match label {
+            Some(label) => format!("<mover><mo>&#x2192;</mo><mtext>{}</mtext></mover>", xml_escape(self.symbols.lookup(label))),
+            None => "<mo>&#x2192;</mo>".to_owned()
+        }
+    }
+
+    pub fn rule(&self, rule: &RewriteRule) -> String {
+        format!("<math><mrow>{}{}{}</mrow></math>", self.expression(&rule.left), self.arrow(rule.label), self.expression(&rule.right))
+    }
+
+    pub fn rules(&self, rules: &RewriteRules) -> String {
+        let rows: String = rules.iter()
+            .map(|rule| format!(
+                "<mtr><mtd>{}</mtd><mtd>{}</mtd><mtd>{}</mtd></mtr>\n",
+                self.expression(&rule.left),
+                self.arrow(rule.label),
+                self.expression(&rule.right)
+            ))
+            .collect();
+        format!("<math display=\"block\"><mtable columnalign=\"right center left\">\n{}</mtable></math>", rows)
+    }
+
+    pub fn derivation(&self, derivation: &Derivation) -> String {
+        let mut rows = String::new();
+        for (idx, step) in derivation.steps.iter().enumerate() {
+            let start = if idx == 0 { self.expression(&derivation.start) } else { String::new() };
+            rows.push_str(&format!(
+                "<mtr><mtd>{}</mtd><mtd><mrow>{}{}</mrow></mtd></mtr>\n",
+                start,
+                self.arrow(step.label),
+                self.expression(&step.term)
+            ));
+        }
+        if derivation.steps.is_empty() {
+            rows.push_str(&format!("<mtr><mtd>{}</mtd></mtr>\n", self.expression(&derivation.start)));
+        }
+        format!("<math display=\"block\"><mtable columnalign=\"right left\">\n{}</mtable></math>", rows)
+    }
+
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_latex_expression() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols [x] + (S 0));
+        assert_eq!(Latex::new(&symbols).expression(&e), "\\mathit{x} + \\left( \\mathrm{S} \\; 0 \\right)");
+    }
+
+    #[test]
+    fn test_latex_notation() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols a * b);
+        let mut notation = Notation::new();
+        notation.set(symbols.handle("*"), "\\cdot");
+        assert_eq!(Latex::with_notation(&symbols, notation).expression(&e), "\\mathrm{a} \\cdot \\mathrm{b}");
+    }
+
+    #[test]
+    fn test_latex_derivation() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let label = symbols.handle("define-one");
+        rules.add(RewriteRule::labeled(Some(label), expr!(symbols 1), expr!(symbols S 0)));
+        let derivation = expr!(symbols 1).derive(&rules, &symbols, 10);
+        assert_eq!(
+            Latex::new(&symbols).derivation(&derivation),
+            "\\begin{align*}\n1 &\\xrightarrow{\\text{define-one}} \\mathrm{S} \\; 0\n\\end{align*}"
+        );
+    }
+
+    #[test]
+    fn test_latex_label() {
+        let mut symbols = SymbolTable::new();
+        let label = symbols.handle("a\\b^c~d_e");
+        let rule = RewriteRule::labeled(Some(label), expr!(symbols 1), expr!(symbols 0));
+        assert_eq!(
+            Latex::new(&symbols).rule(&rule),
+            "1 \\xrightarrow{\\text{a\\textbackslash{}b\\textasciicircum{}c\\textasciitilde{}d\\_e}} 0"
+        );
+    }
+
+    #[test]
+    fn test_mathml_rule() {
+        let mut symbols = SymbolTable::new();
+        let rule = RewriteRule::new(expr!(symbols not true), expr!(symbols false));
+        assert_eq!(
+            MathMl::new(&symbols).rule(&rule),
+            "<math><mrow><mrow><mi mathvariant=\"normal\">not</mi><mi mathvariant=\"normal\">true</mi></mrow>\
+             <mo>&#x2192;</mo><mrow><mi mathvariant=\"normal\">false</mi></mrow></mrow></math>"
+        );
+    }
+
+}
@@ -1,11 +1,92 @@
-use crate::reduce::{RewriteRules, RewriteRule};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::reduce::{ReplacementMap, RewriteRules, RewriteRule, Strategy};
 use crate::expr::*;
 use crate::symbol_table::*;
 use crate::parse::*;
+use crate::pretty::PrettyOptions;
+use crate::render::{Latex, MathMl, Notation};
+use crate::explore::ReductionGraph;
+use crate::dot;
+use crate::trs::Trs;
+use crate::assertion;
+use crate::proof::Proof;
+use crate::graph::TermGraph;
+use crate::profile::{Profile, QueryStats};
+use crate::strategy::{Combinator, Interpreter};
+use crate::signature::Signature;
+use crate::property::{self, Checker};
+use crate::completeness;
+use crate::lint::{self, Lint};
+
+/// Derivations rendered by `:latex` and `:mathml` stop after this many steps
+const MAX_RENDER_STEPS: usize = 1000;
+
+/// `:dot graph` stops exploring after this many distinct terms
+const MAX_GRAPH_NODES: usize = 500;
+
+/// How the REPL evaluates queries and reports results.
+#[derive(Debug, Clone, Default)]
+pub struct ReplOptions {
+    pub strategy: Strategy,
+    /// Queries stop after this many steps, if set
+    pub max_steps: Option<usize>,
+    /// Only print the normal form of each query
+    pub quiet: bool,
+    /// Define rules and check assertions without running queries
+    pub skip_queries: bool,
+    /// Reduce queries as term graphs, sharing the terms that rules duplicate
+    pub sharing: bool,
+    /// Count match attempts and firings per rule, see `Repl::profile_report`
+    pub profile: bool,
+    /// Seeds the random terms `forall` properties are checked with
+    pub seed: u64,
+    pub format: OutputFormat
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// One JSON trace per query, requires the `serde` feature
+    #[cfg(feature = "serde")]
+    Json
+}
 
+/// What happened when executing a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// The line could not be parsed or defined an invalid rule
+    ParseError,
+    /// A query had not reached a normal form when it hit the step limit
+    StepLimit,
+    /// An `assert` or `expect` statement did not hold
+    AssertionFailed
+}
+
+#[derive(Default)]
 pub struct Repl {
     symbols: SymbolTable,
-    rules: RewriteRules
+    rules: RewriteRules,
+    // Strategies named with `strategy name = ...`
+    strategies: HashMap<SymbolHandle, Combinator>,
+    // Declared with `signature ...`, otherwise inferred from the rules
+    signature: Signature,
+    options: ReplOptions,
+    // Assertions that held and failed so far
+    passed: usize,
+    failed: usize,
+    // The proof between `proof` and `qed`, if any
+    proof: Option<Proof>,
+    pretty: PrettyOptions,
+    latex: Notation,
+    mathml: Notation,
+    // Collected while `--profile` or `:profile on` is in effect
+    profile: Option<Profile>,
+    interrupted: Arc<AtomicBool>
 }
 
 impl Repl {
@@ -13,30 +94,533 @@ impl Repl {
     pub fn new() -> Self {
         Repl {
             symbols: SymbolTable::new(),
-            rules: RewriteRules::new()
+            rules: RewriteRules::new(),
+            strategies: HashMap::new(),
+            signature: Signature::default(),
+            options: ReplOptions::default(),
+            passed: 0,
+            failed: 0,
+            proof: None,
+            pretty: PrettyOptions::default(),
+            latex: Notation::new(),
+            mathml: Notation::new(),
+            profile: None,
+            interrupted: Arc::new(AtomicBool::new(false))
         }
     }
 
-    pub fn exec(&mut self, line: &str) {
-        match Item::parse(line, &mut self.symbols) {
+    pub fn with_options(options: ReplOptions) -> Self {
+        let profile = options.profile.then(Profile::default);
+        Repl { options, profile, ..Repl::new() }
+    }
+
+    /// Start a session with the rules of `trs` already defined.
+    pub fn from_trs(trs: Trs, options: ReplOptions) -> Self {
+        let (symbols, rules) = trs.into_parts();
+        let profile = options.profile.then(Profile::default);
+        Repl { symbols, rules, options, profile, ..Repl::new() }
+    }
+
+    /// The rule statistics collected so far, if profiling is enabled.
+    pub fn profile_report(&self) -> Option<String> {
+        self.profile.as_ref().map(|profile| profile.report(&self.rules, &self.symbols))
+    }
+
+    /// A flag that can be raised from another thread (e.g. a Ctrl-C handler)
+    /// to stop the normalization currently running in `exec`.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupted.clone()
+    }
+
+    /// How many assertions held and how many failed.
+    pub fn assertions(&self) -> (usize, usize) {
+        (self.passed, self.failed)
+    }
+
+    /// Every symbol and rule label known to this session.
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.symbols.iter()
+    }
+
+    pub fn exec(&mut self, line: &str) -> Outcome {
+        if let Some(command) = line.trim_start().strip_prefix(':') {
+            self.command(command);
+            return Outcome::Ok;
+        }
+        match Item::parse_complete(line, &mut self.symbols) {
             Ok(item) => {
-                println!("{}", item.bind(&self.symbols));
+                if self.echo() {
+                    println!("{}", item.bind(&self.symbols));
+                }
                 match item.statement {
-                    Statement::Noop => {},
-                    Statement::Rewrite(mut l, r) => {
-                        let s = &mut self.symbols;
-                        if r == expr!(s ?) {
-                            while l.reduce_once(&self.rules) {
-                                println!("\t{}", l.bind(&self.symbols));
-                            }
+                    Statement::Noop => Outcome::Ok,
+                    Statement::Rewrite(l, r, constraints) => {
+                        if r.is_query(&self.symbols) {
+                            if self.options.skip_queries { Outcome::Ok } else { self.query(l) }
                         } else {
-                            self.rules.add(RewriteRule::new(l, r));
+                            let rule = RewriteRule::labeled(item.label.map(|label| label.0), l, r).with_constraints(constraints);
+                            // Strategies defined so far may still apply the rule by its label
+                            let mut calls = vec![];
+                            self.strategies.values().for_each(|strategy| strategy.calls(&mut calls));
+                            match lint::define(&mut self.rules, rule, &self.symbols, &calls) {
+                                Ok(lints) => {
+                                    lints.iter().for_each(|lint| self.warn(lint));
+                                    Outcome::Ok
+                                },
+                                Err(invalid) => {
+                                    eprintln!("Invalid rule: {}", invalid.describe(&self.symbols));
+                                    Outcome::ParseError
+                                }
+                            }
+                        }
+                    },
+                    Statement::Proof(start) => {
+                        if self.proof.is_some() {
+                            eprintln!("The previous proof was never finished with qed");
                         }
+                        self.proof = Some(Proof::new(start));
+                        Outcome::Ok
+                    },
+                    Statement::Step(term, label) => self.step(term, label),
+                    Statement::Qed => self.qed(),
+                    Statement::Strategy(name, strategy) => {
+                        self.strategies.insert(name, strategy);
+                        Outcome::Ok
+                    },
+                    Statement::Apply(strategy, term) => {
+                        if self.options.skip_queries { Outcome::Ok } else { self.apply(&strategy, term) }
+                    },
+                    Statement::Lazy(map) => {
+                        self.rules.add_lazy(map);
+                        Outcome::Ok
+                    },
+                    Statement::Signature(signature) => {
+                        self.signature.extend(signature);
+                        Outcome::Ok
+                    },
+                    Statement::Property(vars, body) => self.property(vars, *body),
+                    ref statement => self.assert(statement)
+                }
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                Outcome::ParseError
+            }
+        }
+    }
+
+    /// Normalize a single term, as if it was queried with `expr -> ?`.
+    pub fn normalize(&mut self, expr: &str) -> Outcome {
+        match Expression::parse_complete(expr, &mut self.symbols) {
+            Ok(term) => {
+                if self.echo() {
+                    println!("{}", term.bind(&self.symbols));
+                }
+                self.query(term)
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                Outcome::ParseError
+            }
+        }
+    }
+
+    fn echo(&self) -> bool {
+        self.options.format == OutputFormat::Text && !self.options.quiet
+    }
+
+    /// Check an `assert` or `expect` statement and report whether it held.
+    fn assert(&mut self, statement: &Statement) -> Outcome {
+        let max_steps = self.options.max_steps.unwrap_or(usize::MAX);
+        let result = assertion::check(statement, &self.rules, &self.symbols, self.options.strategy, max_steps, self.profile.as_mut())
+            .map_err(|failure| failure.describe(&self.symbols));
+        if result.is_ok() {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.report(&statement.bind(&self.symbols).to_string(), result)
+    }
+
+    /// Check a `forall` property on generated terms, reporting the smallest counterexample.
+    fn property(&mut self, vars: Vec<SymbolHandle>, body: Statement) -> Outcome {
+        let max_steps = self.options.max_steps.unwrap_or(property::MAX_STEPS);
+        let inferred;
+        let signature = if self.signature.is_empty() {
+            inferred = Signature::infer(&self.rules);
+            &inferred
+        } else {
+            &self.signature
+        };
+        let mut checker = Checker::new(&self.rules, &self.symbols, signature, self.options.strategy, max_steps);
+        checker.seed = self.options.seed;
+        let result = match checker.check(&vars, &body) {
+            Ok(0) => Err(vec!["no ground terms to check, declare some with signature".to_owned()]),
+            Ok(_) => Ok(()),
+            Err(counterexample) => Err(counterexample.describe(&self.symbols))
+        };
+        if result.is_ok() {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        let what = Statement::Property(vars, Box::new(body)).bind(&self.symbols).to_string();
+        self.report(&what, result)
+    }
+
+    /// Print whether `what` held, with the explanation if it did not.
+    fn report(&self, what: &str, result: Result<(), Vec<String>>) -> Outcome {
+        let outcome = if result.is_ok() { Outcome::Ok } else { Outcome::AssertionFailed };
+        let explanation = result.err().unwrap_or_default();
+
+        #[cfg(feature = "serde")]
+        if self.options.format == OutputFormat::Json {
+            println!("{}", serde_json::json!({
+                "assertion": what,
+                "passed": outcome == Outcome::Ok,
+                "failure": explanation
+            }));
+            return outcome;
+        }
+
+        let status = if outcome == Outcome::Ok { "PASS" } else { "FAIL" };
+        let indent = if self.echo() {
+            println!("\t{}", status);
+            "\t"
+        } else {
+            println!("{} {}", status, what);
+            ""
+        };
+        for line in explanation {
+            println!("{}  {}", indent, line);
+        }
+        outcome
+    }
+
+    /// Print a lint for the rule just defined.
+    fn warn(&self, lint: &Lint) {
+        let earlier = match *lint {
+            Lint::Subsumed(idx) => {
+                let rule = self.rules.get(idx);
+                format!("{} -> {}", rule.left.bind(&self.symbols), rule.right.bind(&self.symbols))
+            },
+            _ => String::new()
+        };
+        eprintln!("Warning: {}", lint.describe(&self.symbols, &earlier));
+    }
+
+    /// Check the next step of the current proof.
+    fn step(&mut self, term: Expression, label: SymbolHandle) -> Outcome {
+        let Some(proof) = &mut self.proof else {
+            eprintln!("Proof steps must follow a proof statement");
+            return Outcome::ParseError;
+        };
+        let what = Statement::Step(term.clone(), label).bind(&self.symbols).to_string();
+        match proof.step(term, label, &self.rules, &self.symbols) {
+            Ok(_) => Outcome::Ok,
+            Err(err) => {
+                let reason = err.describe(label, &self.symbols);
+                self.report(&what, Err(vec![reason]))
+            }
+        }
+    }
+
+    /// Finish the current proof, which holds if every step did.
+    fn qed(&mut self) -> Outcome {
+        let Some(proof) = self.proof.take() else {
+            eprintln!("qed without a proof statement");
+            return Outcome::ParseError;
+        };
+        let what = format!("proof {} = {}", proof.start.bind(&self.symbols), proof.current.bind(&self.symbols));
+        if proof.failures == 0 {
+            self.passed += 1;
+            self.report(&what, Ok(()))
+        } else {
+            self.failed += 1;
+            self.report(&what, Err(vec![format!("{} of its steps do not follow", proof.failures)]))
+        }
+    }
+
+    /// Reduce `term` to its normal form, printing each step.
+    fn query(&mut self, mut term: Expression) -> Outcome {
+        let strategy = self.options.strategy;
+        let max_steps = self.options.max_steps.unwrap_or(usize::MAX);
+
+        #[cfg(feature = "serde")]
+        if self.options.format == OutputFormat::Json {
+            use crate::serialize::{to_json, Trace};
+            let derivation = term.derive_profiled(&self.rules, &self.symbols, max_steps, strategy, self.profile.as_mut());
+            match to_json(&Trace::resolve(&derivation, &self.symbols)) {
+                Ok(json) => println!("{}", json),
+                Err(err) => eprintln!("{}", err)
+            }
+            if let Some(profile) = &mut self.profile {
+                let mut stats = QueryStats::new(&term);
+                for step in &derivation.steps {
+                    stats.step(step.term.size());
+                }
+                profile.queries.push(stats);
+            }
+            return if derivation.truncated { Outcome::StepLimit } else { Outcome::Ok };
+        }
+
+        if self.options.sharing && TermGraph::supports(&term, &self.rules) {
+            return self.query_shared(term);
+        }
+
+        self.interrupted.store(false, Ordering::SeqCst);
+        let mut stats = self.profile.is_some().then(|| QueryStats::new(&term));
+        let mut outcome = Outcome::Ok;
+        let mut steps = 0;
+        while term.rewrite_once_profiled(&self.rules, &self.symbols, strategy, self.profile.as_mut()).is_some() {
+            steps += 1;
+            if let Some(stats) = &mut stats {
+                stats.step(term.size());
+            }
+            if self.echo() {
+                println!("\t{}", term.pretty(&self.symbols, &self.pretty).replace('\n', "\n\t"));
+            }
+            if self.interrupted.swap(false, Ordering::SeqCst) {
+                println!("<INTERRUPTED>");
+                break;
+            }
+            if steps >= max_steps {
+                // A term reached on the last step may already be in normal form
+                if term.clone().rewrite_once_with(&self.rules, &self.symbols, strategy).is_some() {
+                    outcome = Outcome::StepLimit;
+                }
+                break;
+            }
+        }
+        if self.options.quiet {
+            println!("{}", term.pretty(&self.symbols, &self.pretty));
+        }
+        self.finish_query(stats, outcome, steps)
+    }
+
+    /// Like `query`, but duplicated subterms are shared and printed once.
+    fn query_shared(&mut self, term: Expression) -> Outcome {
+        let max_steps = self.options.max_steps.unwrap_or(usize::MAX);
+        let mut graph = TermGraph::new(&term);
+        self.interrupted.store(false, Ordering::SeqCst);
+        // Sizes count shared subterms once
+        let mut stats = self.profile.is_some().then(|| {
+            let size = graph.size();
+            QueryStats { start_size: size, max_size: size, final_size: size, ..QueryStats::new(&term) }
+        });
+        let mut outcome = Outcome::Ok;
+        let mut steps = 0;
+        while graph.rewrite_once(&self.rules, &self.symbols, self.options.strategy, self.profile.as_mut()) {
+            steps += 1;
+            if let Some(stats) = &mut stats {
+                stats.step(graph.size());
+            }
+            if self.echo() {
+                println!("\t{}", graph.bind(&self.symbols));
+            }
+            if self.interrupted.swap(false, Ordering::SeqCst) {
+                println!("<INTERRUPTED>");
+                break;
+            }
+            if steps >= max_steps {
+                if graph.clone().rewrite_once(&self.rules, &self.symbols, self.options.strategy, None) {
+                    outcome = Outcome::StepLimit;
+                }
+                break;
+            }
+        }
+        if self.options.quiet {
+            println!("{}", graph.bind(&self.symbols));
+        }
+        self.finish_query(stats, outcome, steps)
+    }
+
+    /// Record the term sizes of a query when profiling and report the step limit.
+    fn finish_query(&mut self, stats: Option<QueryStats>, outcome: Outcome, steps: usize) -> Outcome {
+        if let (Some(profile), Some(stats)) = (&mut self.profile, stats) {
+            profile.queries.push(stats);
+        }
+        if outcome == Outcome::StepLimit {
+            eprintln!("Stopped after {} steps without reaching a normal form", steps);
+        }
+        outcome
+    }
+
+    /// Rewrite `term` with `strategy`, printing the result or `fail`.
+    fn apply(&mut self, strategy: &Combinator, term: Expression) -> Outcome {
+        let max_steps = self.options.max_steps.unwrap_or(usize::MAX);
+        let mut interpreter = Interpreter::new(&self.rules, &self.strategies, &self.symbols, max_steps);
+        let result = interpreter.apply(strategy, &term);
+        let outcome = if interpreter.exhausted { Outcome::StepLimit } else { Outcome::Ok };
+
+        #[cfg(feature = "serde")]
+        if self.options.format == OutputFormat::Json {
+            println!("{}", serde_json::json!({
+                "apply": Statement::Apply(strategy.clone(), term).bind(&self.symbols).to_string(),
+                "result": result.map(|t| t.bind(&self.symbols).to_string()),
+                "steps": interpreter.steps,
+                "truncated": interpreter.exhausted
+            }));
+            return outcome;
+        }
+
+        let indent = if self.echo() { "\t" } else { "" };
+        match result {
+            Some(term) => println!("{}{}", indent, term.pretty(&self.symbols, &self.pretty).replace('\n', &format!("\n{}", indent))),
+            None if interpreter.exhausted => eprintln!("Stopped after {} steps, the strategy hit the step or nesting limit", interpreter.steps),
+            None => println!("{}fail", indent)
+        }
+        outcome
+    }
+
+    /// Handle a `:command` that configures the session rather than
+    /// defining rules or running queries.
+    fn command(&mut self, command: &str) {
+        let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let arg = arg.trim();
+        match name {
+            "width" => match arg.parse() {
+                Ok(width) => self.pretty.width = width,
+                Err(_) => eprintln!("Usage: :width <columns>")
+            },
+            "parens" => match arg {
+                "minimal" => self.pretty.minimal_parens = true,
+                "all" => self.pretty.minimal_parens = false,
+                _ => eprintln!("Usage: :parens minimal|all")
+            },
+            "lazy" => match ReplacementMap::parse_complete(arg, &mut self.symbols) {
+                Ok(map) => self.rules.add_lazy(map),
+                Err(err) => eprintln!("{}", err)
+            },
+            "complete" => {
+                let signature = if self.signature.is_empty() { Signature::infer(&self.rules) } else { self.signature.clone() };
+                let missing = completeness::check(&self.rules, signature, &self.symbols);
+                if missing.is_empty() {
+                    println!("Every defined symbol has a rule for each constructor");
+                }
+                for case in missing {
+                    println!("No rule for {}", case);
+                }
+            },
+            "profile" => match arg {
+                "" => match self.profile_report() {
+                    Some(report) => print!("{}", report),
+                    None => eprintln!("Profiling is off, start it with :profile on")
+                },
+                "on" => {
+                    self.profile.get_or_insert_with(Profile::default);
+                },
+                "off" => self.profile = None,
+                "reset" => self.profile = Some(Profile::default()),
+                _ => eprintln!("Usage: :profile [on|off|reset]")
+            },
+            "share" => match arg {
+                "on" => self.options.sharing = true,
+                "off" => self.options.sharing = false,
+                _ => eprintln!("Usage: :share on|off")
+            },
+            "tree" => match Expression::parse(arg, &mut self.symbols) {
+                Ok(expr) => print!("{}", expr.tree(&self.symbols)),
+                Err(err) => eprintln!("{}", err)
+            },
+            "latex" | "mathml" => match Expression::parse(arg, &mut self.symbols) {
+                Ok(expr) => {
+                    let derivation = expr.derive(&self.rules, &self.symbols, MAX_RENDER_STEPS);
+                    if name == "latex" {
+                        println!("{}", Latex::with_notation(&self.symbols, self.latex.clone()).derivation(&derivation));
+                    } else {
+                        println!("{}", MathMl::with_notation(&self.symbols, self.mathml.clone()).derivation(&derivation));
                     }
+                },
+                Err(err) => eprintln!("{}", err)
+            },
+            "dot" => {
+                let (kind, src) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+                let expr = match Expression::parse(src, &mut self.symbols) {
+                    Ok(expr) => expr,
+                    Err(err) => return eprintln!("{}", err)
+                };
+                match kind {
+                    "tree" => print!("{}", dot::tree_to_dot(&expr, &self.symbols)),
+                    "trace" => {
+                        let derivation = expr.derive(&self.rules, &self.symbols, MAX_RENDER_STEPS);
+                        print!("{}", dot::derivation_to_dot(&derivation, &self.rules, &self.symbols));
+                    },
+                    "graph" => {
+                        let graph = ReductionGraph::explore(&expr, &self.rules, &self.symbols, MAX_GRAPH_NODES);
+                        if !graph.complete {
+                            eprintln!("Stopped exploring after {} terms", MAX_GRAPH_NODES);
+                        }
+                        print!("{}", dot::graph_to_dot(&graph, &self.rules, &self.symbols));
+                    },
+                    _ => eprintln!("Usage: :dot tree|trace|graph <expr>")
                 }
             },
-            Err(err) => eprintln!("{}", err)
+            "notation" => {
+                let mut parts = arg.splitn(3, char::is_whitespace);
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some("latex"), Some(symbol), Some(notation)) => self.latex.set(self.symbols.handle(symbol), notation.trim()),
+                    (Some("mathml"), Some(symbol), Some(notation)) => self.mathml.set(self.symbols.handle(symbol), notation.trim()),
+                    _ => eprintln!("Usage: :notation latex|mathml <symbol> <notation>")
+                }
+            },
+            _ => eprintln!("Unknown command :{}", name)
+        }
+    }
+
+}
+
+/// Returns true if `line` opens more parentheses than it closes,
+/// meaning the statement continues on the next line.
+/// Anything after a `//` comment is ignored.
+pub fn is_incomplete(line: &str) -> bool {
+    let code = match line.find("//") {
+        Some(idx) => &line[..idx],
+        None => line
+    };
+    let mut depth = 0;
+    for c in code.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_incomplete_parens() {
+        assert!(is_incomplete("(a + (b"));
+        assert!(!is_incomplete("(a + b) -> c"));
+        assert!(!is_incomplete("a -> b // (unbalanced comment"));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        for sharing in [false, true] {
+            let options = ReplOptions { quiet: true, max_steps: Some(1), sharing, ..ReplOptions::default() };
+            let trs = Trs::from_source("1 -> S 0\n$x + 0 -> $x").unwrap();
+            let mut repl = Repl::from_trs(trs, options);
+            // The normal form is reached on the last allowed step
+            assert_eq!(repl.normalize("1"), Outcome::Ok);
+            assert_eq!(repl.normalize("1 + 0"), Outcome::StepLimit);
         }
     }
 
+    #[test]
+    fn test_trailing_input() {
+        let options = ReplOptions { quiet: true, ..ReplOptions::default() };
+        let mut repl = Repl::with_options(options);
+        // Rejected like `Trs::load` and `peano check` do
+        assert_eq!(repl.exec("a -> b ) c"), Outcome::ParseError);
+        assert_eq!(repl.exec("a -> b // comment"), Outcome::Ok);
+        assert_eq!(repl.rules.len(), 1);
+    }
+
 }
@@ -0,0 +1,439 @@
+//! Serializable mirrors of terms, rules and traces.
+//!
+//! A `SymbolHandle` only means something together with its `SymbolTable`,
+//! so these types carry symbol names instead. Use `resolve` to convert
+//! from the engine's types and `intern` to convert back into a table.
+
+use std::{error::Error, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::expr::*;
+use crate::reduce::{Derivation, DerivationStep, ReplacementMap, RewriteRule, RewriteRules, Slot};
+use crate::symbol_table::{NamePattern, SymbolTable};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Term {
+    Symbol(String),
+    Variable { name: String, distinct: bool },
+    /// A variable with a guard, kept apart so unguarded variables encode as before
+    GuardedVariable { name: String, distinct: bool, guard: VariableGuard },
+    Parentheses(Expr),
+    Binder { name: String, body: Box<Term> },
+    /// A de Bruijn index, 0 refers to the innermost binder
+    Bound(usize),
+    Substitution { name: String, argument: Box<Term> }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariableGuard {
+    Symbol,
+    Compound,
+    Integer,
+    Regex(RegexSource),
+    OneOf(Vec<String>)
+}
+
+/// A regex as written in a guard, checked when it is deserialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RegexSource(String);
+
+impl TryFrom<String> for RegexSource {
+    type Error = regex::Error;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        NamePattern::regex(&source)?;
+        Ok(RegexSource(source))
+    }
+}
+
+impl From<RegexSource> for String {
+    fn from(source: RegexSource) -> Self {
+        source.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Expr(pub Vec<Term>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub left: Expr,
+    pub right: Expr,
+    // Always written, bincode can not skip fields
+    #[serde(default)]
+    pub constraints: Vec<RuleConstraint>
+}
+
+/// A constraint from the `where` clause of a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleConstraint {
+    NotEqual { left: Term, right: Term },
+    NotMatching { term: Term, pattern: Term }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+    /// Each `:lazy` declaration, one slot per word
+    #[serde(default)]
+    pub lazy: Vec<Vec<LazySlot>>
+}
+
+/// A word of a `:lazy` declaration, tagged so that symbols named
+/// `!` or `_` are not mistaken for arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LazySlot {
+    Symbol(String),
+    /// `!`, an argument that is rewritten
+    Active,
+    /// `_`, an argument that is left alone
+    Frozen
+}
+
+/// A term to be normalized, the `expr -> ?` of a rule file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Query {
+    pub term: Expr
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub start: Expr,
+    pub steps: Vec<TraceStep>,
+    pub truncated: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Index of the applied rule in its rule set
+    pub rule: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub position: Vec<usize>,
+    pub term: Expr
+}
+
+impl Term {
+
+    pub fn resolve(term: &Terminal, symbols: &SymbolTable) -> Self {
+        match term {
+            Terminal::Symbol(s) => Term::Symbol(symbols.lookup(*s).to_owned()),
+            Terminal::Variable(v, k, None) => Term::Variable {
+                name: symbols.lookup(*v).to_owned(),
+                distinct: *k == VariableKind::Distinct
+            },
+            Terminal::Variable(v, k, Some(guard)) => Term::GuardedVariable {
+                name: symbols.lookup(*v).to_owned(),
+                distinct: *k == VariableKind::Distinct,
+                guard: VariableGuard::resolve(guard, symbols)
+            },
+            Terminal::Parentheses(e) => Term::Parentheses(Expr::resolve(e, symbols)),
+            Terminal::Binder(hint, body) => Term::Binder {
+                name: symbols.lookup(hint.0).to_owned(),
+                body: Box::new(Term::resolve(body, symbols))
+            },
+            Terminal::Bound(idx) => Term::Bound(*idx),
+            Terminal::Substitution(v, arg) => Term::Substitution {
+                name: symbols.lookup(*v).to_owned(),
+                argument: Box::new(Term::resolve(arg, symbols))
+            }
+        }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Terminal {
+        match self {
+            Term::Symbol(s) => Terminal::Symbol(symbols.handle(s)),
+            Term::Variable { name, distinct } => {
+                let kind = if *distinct { VariableKind::Distinct } else { VariableKind::Any };
+                Terminal::Variable(symbols.handle(name), kind, None)
+            },
+            Term::GuardedVariable { name, distinct, guard } => {
+                let kind = if *distinct { VariableKind::Distinct } else { VariableKind::Any };
+                let guard = Box::new(guard.intern(symbols));
+                Terminal::Variable(symbols.handle(name), kind, Some(guard))
+            },
+            Term::Parentheses(e) => Terminal::Parentheses(e.intern(symbols)),
+            Term::Binder { name, body } => Terminal::Binder(Hint(symbols.handle(name)), Box::new(body.intern(symbols))),
+            Term::Bound(idx) => Terminal::Bound(*idx),
+            Term::Substitution { name, argument } => Terminal::Substitution(symbols.handle(name), Box::new(argument.intern(symbols)))
+        }
+    }
+
+}
+
+impl VariableGuard {
+
+    pub fn resolve(guard: &Guard, symbols: &SymbolTable) -> Self {
+        match guard {
+            Guard::Symbol => VariableGuard::Symbol,
+            Guard::Compound => VariableGuard::Compound,
+            Guard::Name(pattern) => match pattern {
+                NamePattern::Integer => VariableGuard::Integer,
+                NamePattern::Regex(source) => VariableGuard::Regex(RegexSource(source.clone()))
+            },
+            Guard::OneOf(members) => VariableGuard::OneOf(members.iter().map(|m| symbols.lookup(*m).to_owned()).collect())
+        }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Guard {
+        match self {
+            VariableGuard::Symbol => Guard::Symbol,
+            VariableGuard::Compound => Guard::Compound,
+            VariableGuard::Integer => Guard::Name(NamePattern::Integer),
+            VariableGuard::Regex(RegexSource(source)) => {
+                let pattern = symbols.regex(source).expect("regex sources are validated when deserialized");
+                Guard::Name(pattern)
+            },
+            VariableGuard::OneOf(members) => Guard::OneOf(members.iter().map(|m| symbols.handle(m)).collect())
+        }
+    }
+
+}
+
+impl Expr {
+
+    pub fn resolve(expr: &Expression, symbols: &SymbolTable) -> Self {
+        Expr(expr.0.iter().map(|t| Term::resolve(t, symbols)).collect())
+    }
+
+    /// The expression in canonical form, whatever parentheses were serialized.
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Expression {
+        Expression(self.0.iter().map(|t| t.intern(symbols)).collect()).without_redundant_parens()
+    }
+
+}
+
+impl Rule {
+
+    pub fn resolve(rule: &RewriteRule, symbols: &SymbolTable) -> Self {
+        Rule {
+            label: rule.label.map(|l| symbols.lookup(l).to_owned()),
+            left: Expr::resolve(&rule.left, symbols),
+            right: Expr::resolve(&rule.right, symbols),
+            constraints: rule.constraints.iter().map(|c| RuleConstraint::resolve(c, symbols)).collect()
+        }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> RewriteRule {
+        let label = self.label.as_ref().map(|l| symbols.handle(l));
+        let constraints = self.constraints.iter().map(|c| c.intern(symbols)).collect();
+        RewriteRule::labeled(label, self.left.intern(symbols), self.right.intern(symbols))
+            .with_constraints(constraints)
+    }
+
+}
+
+impl RuleConstraint {
+
+    pub fn resolve(constraint: &Constraint, symbols: &SymbolTable) -> Self {
+        match constraint {
+            Constraint::NotEqual(a, b) => RuleConstraint::NotEqual {
+                left: Term::resolve(a, symbols),
+                right: Term::resolve(b, symbols)
+            },
+            Constraint::NotMatching(a, b) => RuleConstraint::NotMatching {
+                term: Term::resolve(a, symbols),
+                pattern: Term::resolve(b, symbols)
+            }
+        }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Constraint {
+        match self {
+            RuleConstraint::NotEqual { left, right } => Constraint::NotEqual(left.intern(symbols), right.intern(symbols)),
+            RuleConstraint::NotMatching { term, pattern } => Constraint::NotMatching(term.intern(symbols), pattern.intern(symbols))
+        }
+    }
+
+}
+
+impl RuleSet {
+
+    pub fn resolve(rules: &RewriteRules, symbols: &SymbolTable) -> Self {
+        let lazy = rules.lazy()
+            .map(|map| map.0.iter().map(|slot| match slot {
+                Slot::Symbol(s) => LazySlot::Symbol(symbols.lookup(*s).to_owned()),
+                Slot::Active => LazySlot::Active,
+                Slot::Frozen => LazySlot::Frozen
+            }).collect())
+            .collect();
+        RuleSet { rules: rules.iter().map(|r| Rule::resolve(r, symbols)).collect(), lazy }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> RewriteRules {
+        let mut rules = RewriteRules::new();
+        for rule in &self.rules {
+            rules.add(rule.intern(symbols));
+        }
+        for slots in &self.lazy {
+            rules.add_lazy(ReplacementMap(slots.iter().map(|slot| match slot {
+                LazySlot::Symbol(s) => Slot::Symbol(symbols.handle(s)),
+                LazySlot::Active => Slot::Active,
+                LazySlot::Frozen => Slot::Frozen
+            }).collect()));
+        }
+        rules
+    }
+
+}
+
+impl Query {
+
+    pub fn resolve(term: &Expression, symbols: &SymbolTable) -> Self {
+        Query { term: Expr::resolve(term, symbols) }
+    }
+
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Expression {
+        self.term.intern(symbols)
+    }
+
+}
+
+impl Trace {
+
+    pub fn resolve(derivation: &Derivation, symbols: &SymbolTable) -> Self {
+        Trace {
+            start: Expr::resolve(&derivation.start, symbols),
+            steps: derivation.steps.iter()
+                .map(|step| TraceStep {
+                    rule: step.rule,
+                    label: step.label.map(|l| symbols.lookup(l).to_owned()),
+                    position: step.position.clone(),
+                    term: Expr::resolve(&step.term, symbols)
+                })
+                .collect(),
+            truncated: derivation.truncated
+        }
+    }
+
+    /// The derivation this trace was resolved from. Rule indices are
+    /// kept as they are, so they only make sense with the same rule set.
+    pub fn intern(&self, symbols: &mut SymbolTable) -> Derivation {
+        Derivation {
+            start: self.start.intern(symbols),
+            steps: self.steps.iter()
+                .map(|step| DerivationStep {
+                    rule: step.rule,
+                    label: step.label.as_ref().map(|l| symbols.handle(l)),
+                    position: step.position.clone(),
+                    term: step.term.intern(symbols)
+                })
+                .collect(),
+            truncated: self.truncated
+        }
+    }
+
+}
+
+#[derive(Debug)]
+pub enum SerializeError {
+    Json(serde_json::Error),
+    Binary(bincode::Error)
+}
+
+impl Error for SerializeError {}
+
+impl Display for SerializeError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerializeError::Json(e) => write!(f, "JSON error: {}", e),
+            SerializeError::Binary(e) => write!(f, "Binary encoding error: {}", e)
+        }
+    }
+
+}
+
+pub fn to_json<T: Serialize>(value: &T) -> Result<String, SerializeError> {
+    serde_json::to_string(value).map_err(SerializeError::Json)
+}
+
+pub fn from_json<'de, T: Deserialize<'de>>(s: &'de str) -> Result<T, SerializeError> {
+    serde_json::from_str(s).map_err(SerializeError::Json)
+}
+
+/// A compact binary encoding, see `bincode` for the format.
+pub fn to_binary<T: Serialize>(value: &T) -> Result<Vec<u8>, SerializeError> {
+    bincode::serialize(value).map_err(SerializeError::Binary)
+}
+
+pub fn from_binary<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, SerializeError> {
+    bincode::deserialize(bytes).map_err(SerializeError::Binary)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_term_json() {
+        let mut symbols = SymbolTable::new();
+        let e = expr!(symbols S ([x] + 0));
+        assert_eq!(
+            to_json(&Expr::resolve(&e, &symbols)).unwrap(),
+            r#"[{"symbol":"S"},{"parentheses":[{"variable":{"name":"x","distinct":false}},{"symbol":"+"},{"symbol":"0"}]}]"#
+        );
+    }
+
+    #[test]
+    fn test_rules_round_trip() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let label = symbols.handle("define-addition-1");
+        rules.add(RewriteRule::labeled(Some(label), expr!(symbols [x] + (S [y])), expr!(symbols S ([x] + [y]))));
+        let set = RuleSet::resolve(&rules, &symbols);
+
+        let json = to_json(&set).unwrap();
+        assert_eq!(from_json::<RuleSet>(&json).unwrap(), set);
+        let bytes = to_binary(&set).unwrap();
+        assert_eq!(from_binary::<RuleSet>(&bytes).unwrap(), set);
+
+        // Interning into a fresh table yields the same rules by name
+        let mut other = SymbolTable::new();
+        other.handle("unrelated");
+        let interned = set.intern(&mut other);
+        assert_eq!(RuleSet::resolve(&interned, &other), set);
+    }
+
+    #[test]
+    fn test_lazy_symbols() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        // A symbol that happens to be spelled like an argument marker
+        rules.add_lazy(ReplacementMap(vec![Slot::Symbol(symbols.handle("!")), Slot::Active, Slot::Frozen]));
+        let set = RuleSet::resolve(&rules, &symbols);
+        assert_eq!(to_json(&set.lazy).unwrap(), r#"[[{"symbol":"!"},"active","frozen"]]"#);
+        let interned = from_json::<RuleSet>(&to_json(&set).unwrap()).unwrap().intern(&mut symbols);
+        assert_eq!(RuleSet::resolve(&interned, &symbols), set);
+    }
+
+    #[test]
+    fn test_trace_round_trip() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let label = symbols.handle("define-one");
+        rules.add(RewriteRule::labeled(Some(label), expr!(symbols 1), expr!(symbols S 0)));
+        let derivation = expr!(symbols 1 + 1).derive(&rules, &symbols, 10);
+        let trace = Trace::resolve(&derivation, &symbols);
+        let trace = from_json::<Trace>(&to_json(&trace).unwrap()).unwrap();
+
+        let mut other = SymbolTable::new();
+        other.handle("unrelated");
+        let interned = trace.intern(&mut other);
+        assert_eq!(interned.steps.len(), 2);
+        assert_eq!(interned.steps[0].label.map(|l| other.lookup(l)), Some("define-one"));
+        assert_eq!(interned.result().bind(&other).to_string(), "(S 0) + (S 0)");
+        assert_eq!(Trace::resolve(&interned, &other), trace);
+    }
+
+}
@@ -0,0 +1,299 @@
+//! The symbols ground terms are built from, for testing rule sets on
+//! terms nobody wrote down.
+//!
+//! A signature is a list of constants like `0` and shapes like `S _`
+//! or `_ + _`, where each `_` is an argument. It is either declared
+//! with `signature 0, S _, _ + _` or inferred from the rules.
+
+use crate::expr::*;
+use crate::reduce::RewriteRules;
+use crate::symbol_table::SymbolHandle;
+
+/// A compound term with `None` for each argument, like `_ + _`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Shape(pub Vec<Option<SymbolHandle>>);
+
+impl Shape {
+
+    pub fn arity(&self) -> usize {
+        self.0.iter().filter(|slot| slot.is_none()).count()
+    }
+
+    /// The size of the smallest term with this shape.
+    pub fn min_size(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The term with this shape and `args` in place of the arguments.
+    pub fn build(&self, args: &[Terminal]) -> Terminal {
+        let mut args = args.iter();
+        let terms = self.0.iter()
+            .map(|slot| match slot {
+                Some(s) => Terminal::Symbol(*s),
+                None => args.next().expect("an argument for every slot").clone()
+            })
+            .collect();
+        Terminal::Parentheses(Expression(terms))
+    }
+
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub constants: Vec<SymbolHandle>,
+    pub shapes: Vec<Shape>
+}
+
+impl Signature {
+
+    /// Infer a signature from the terms in `rules`. Constants are the
+    /// symbols that make up a whole term somewhere, such as `0` in
+    /// `$x * 0 -> 0`, or stand where another term has an argument, such
+    /// as `0` in `$x + 0` next to `$x + (S $y)`. Every compound term
+    /// gives a shape, with its variables, parentheses and constants as
+    /// the arguments.
+    pub fn infer(rules: &RewriteRules) -> Self {
+        let mut constants = vec![];
+        let mut patterns = vec![];
+        for rule in rules.iter() {
+            collect(&rule.left, &mut constants, &mut patterns);
+            collect(&rule.right, &mut constants, &mut patterns);
+        }
+        // Until no more are found, as a new constant can stand where another symbol is
+        let mut found = true;
+        while found {
+            found = false;
+            for p in &patterns {
+                for q in patterns.iter().filter(|q| q.len() == p.len()) {
+                    for (idx, term) in p.iter().enumerate() {
+                        if let Terminal::Symbol(s) = term {
+                            if !constants.contains(s) && is_argument(&q[idx], &constants) && same_keywords(p, q, idx, &constants) {
+                                constants.push(*s);
+                                found = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut signature = Signature { constants, shapes: vec![] };
+        for pattern in patterns {
+            let shape = signature.shape_of(pattern);
+            if shape.arity() > 0 {
+                signature.add_shape(shape);
+            }
+        }
+        signature
+    }
+
+    /// The shape of a compound term, with everything but
+    /// the symbols that are not constants as arguments.
+    pub fn shape_of(&self, terms: &[Terminal]) -> Shape {
+        Shape(terms.iter()
+            .map(|term| match term {
+                Terminal::Symbol(s) if !self.constants.contains(s) => Some(*s),
+                _ => None
+            })
+            .collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constants.is_empty() && self.shapes.is_empty()
+    }
+
+    pub fn add_constant(&mut self, constant: SymbolHandle) {
+        if !self.constants.contains(&constant) {
+            self.constants.push(constant);
+        }
+    }
+
+    pub fn add_shape(&mut self, shape: Shape) {
+        if !self.shapes.contains(&shape) {
+            self.shapes.push(shape);
+        }
+    }
+
+    /// Add the constants and shapes of `other` that are not part of this signature yet.
+    pub fn extend(&mut self, other: Signature) {
+        other.constants.into_iter().for_each(|c| self.add_constant(c));
+        other.shapes.into_iter().for_each(|s| self.add_shape(s));
+    }
+
+    /// Every ground term of at most `max_size` symbols, the smallest first,
+    /// stopping after `limit` terms.
+    pub fn enumerate(&self, max_size: usize, limit: usize) -> Vec<Terminal> {
+        // The terms of each size, built from the smaller ones
+        let mut by_size: Vec<Vec<Terminal>> = vec![vec![]; max_size + 1];
+        let mut count = 0;
+        for size in 1..=max_size {
+            let mut terms = vec![];
+            if size == 1 {
+                terms.extend(self.constants.iter().map(|c| Terminal::Symbol(*c)));
+            }
+            for shape in &self.shapes {
+                if shape.arity() > 0 && shape.min_size() <= size {
+                    let budget = size - (shape.min_size() - shape.arity());
+                    fill(shape, &by_size, budget, &mut vec![], &mut terms, limit - count);
+                }
+            }
+            terms.truncate(limit - count);
+            count += terms.len();
+            by_size[size] = terms;
+            if count >= limit {
+                break;
+            }
+        }
+        by_size.into_iter().flatten().collect()
+    }
+
+    /// A random ground term of at most `max_size` symbols,
+    /// or None if there are no constants to build one from.
+    pub fn sample(&self, rng: &mut Rng, max_size: usize) -> Option<Terminal> {
+        if self.constants.is_empty() {
+            return None;
+        }
+        let fitting: Vec<&Shape> = self.shapes.iter()
+            .filter(|shape| shape.arity() > 0 && shape.min_size() <= max_size)
+            .collect();
+        let pick = rng.below(self.constants.len() + fitting.len());
+        if pick < self.constants.len() {
+            return Some(Terminal::Symbol(self.constants[pick]));
+        }
+        let shape = fitting[pick - self.constants.len()];
+        // Each argument takes at least one symbol, the rest is shared out at random
+        let mut spare = max_size - shape.min_size();
+        let mut args = vec![];
+        for _ in 0..shape.arity() {
+            let extra = rng.below(spare + 1);
+            spare -= extra;
+            args.push(self.sample(rng, 1 + extra)?);
+        }
+        Some(shape.build(&args))
+    }
+
+}
+
+fn collect<'e>(expr: &'e Expression, constants: &mut Vec<SymbolHandle>, patterns: &mut Vec<&'e [Terminal]>) {
+    match expr.0.as_slice() {
+        [Terminal::Symbol(s)] => if !constants.contains(s) {
+            constants.push(*s);
+        },
+        [_] => {},
+        terms => patterns.push(terms)
+    }
+    for term in &expr.0 {
+        collect_terminal(term, constants, patterns);
+    }
+}
+
+fn collect_terminal<'e>(term: &'e Terminal, constants: &mut Vec<SymbolHandle>, patterns: &mut Vec<&'e [Terminal]>) {
+    match term {
+        Terminal::Parentheses(e) => collect(e, constants, patterns),
+        Terminal::Binder(_, body) | Terminal::Substitution(_, body) => collect_terminal(body, constants, patterns),
+        _ => {}
+    }
+}
+
+fn is_argument(term: &Terminal, constants: &[SymbolHandle]) -> bool {
+    match term {
+        Terminal::Symbol(s) => constants.contains(s),
+        _ => true
+    }
+}
+
+/// Whether `p` and `q` have the same symbols wherever neither has an
+/// argument, apart from at `skip`, and have at least one in common.
+fn same_keywords(p: &[Terminal], q: &[Terminal], skip: usize, constants: &[SymbolHandle]) -> bool {
+    let keywords = |idx: usize| idx != skip && !is_argument(&p[idx], constants) && !is_argument(&q[idx], constants);
+    (0..p.len()).filter(|idx| keywords(*idx)).all(|idx| p[idx] == q[idx])
+        && (0..p.len()).any(keywords)
+}
+
+/// Add every term of `shape` whose arguments take `budget` symbols in total to `out`.
+fn fill(shape: &Shape, by_size: &[Vec<Terminal>], budget: usize, args: &mut Vec<Terminal>, out: &mut Vec<Terminal>, limit: usize) {
+    if out.len() >= limit {
+        return;
+    }
+    let left = shape.arity() - args.len();
+    if left == 0 {
+        if budget == 0 {
+            out.push(shape.build(args));
+        }
+        return;
+    }
+    // Leave at least one symbol for each of the other arguments
+    for size in 1..=budget.saturating_sub(left - 1) {
+        for arg in &by_size[size] {
+            args.push(arg.clone());
+            fill(shape, by_size, budget - size, args, out, limit);
+            args.pop();
+        }
+    }
+}
+
+/// A small deterministic random number generator (SplitMix64),
+/// so that sampled terms are the same for the same seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` must not be 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::reduce::RewriteRule;
+    use crate::symbol_table::SymbolTable;
+    use super::*;
+
+    #[test]
+    fn test_infer() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        rules.add(RewriteRule::new(expr!(symbols [x] + 0), expr!(symbols [x])));
+        rules.add(RewriteRule::new(expr!(symbols [x] + (S [y])), expr!(symbols S ([x] + [y]))));
+        let signature = Signature::infer(&rules);
+        let display: Vec<String> = signature.shapes.iter().map(|s| s.bind(&symbols).to_string()).collect();
+        assert_eq!(signature.constants, vec![symbols.handle("0")]);
+        assert_eq!(display, vec!["_ + _", "S _"]);
+    }
+
+    #[test]
+    fn test_enumerate() {
+        let mut symbols = SymbolTable::new();
+        let zero = symbols.handle("0");
+        let s = symbols.handle("S");
+        let plus = symbols.handle("+");
+        let signature = Signature {
+            constants: vec![zero],
+            shapes: vec![Shape(vec![Some(s), None]), Shape(vec![None, Some(plus), None])]
+        };
+        let terms: Vec<String> = signature.enumerate(4, 100).iter().map(|t| t.bind(&symbols).to_string()).collect();
+        assert_eq!(terms, vec!["0", "(S 0)", "(S (S 0))", "(0 + 0)", "(S (S (S 0)))", "(S (0 + 0))", "(0 + (S 0))", "((S 0) + 0)"]);
+        assert_eq!(signature.enumerate(4, 3).len(), 3);
+
+        let mut rng = Rng::new(7);
+        for _ in 0..20 {
+            assert!(signature.sample(&mut rng, 5).unwrap().size() <= 5);
+        }
+    }
+
+}
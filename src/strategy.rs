@@ -0,0 +1,257 @@
+//! User-defined strategies.
+//!
+//! Instead of applying every rule wherever it matches, a strategy
+//! decides which rules are tried where and in which order. Strategies
+//! either succeed with a new term or fail, and are combined in the
+//! style of Stratego:
+//!
+//! ```text
+//! strategy simplify = repeat(oneOf(define-addition-*))
+//! apply bottomup(try(define-one)): 1 + 1
+//! ```
+
+use std::collections::HashMap;
+
+use crate::binder::{body_expression, expression_body};
+use crate::expr::*;
+use crate::interpolate::Interpolate;
+use crate::reduce::RewriteRules;
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+/// Strategies nested deeper than this fail, which stops runaway recursion
+/// like `strategy loop = loop`.
+pub const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Combinator {
+    /// A named strategy, or else the rules with this label applied to
+    /// the whole term. `define-addition-*` selects every label starting
+    /// with `define-addition-`.
+    Call(SymbolHandle),
+    /// Succeeds without changing the term
+    Id,
+    /// Always fails
+    Fail,
+    /// `try(s)`, applies `s` if it succeeds
+    Try(Box<Combinator>),
+    /// `repeat(s)`, applies `s` until it fails or no longer changes the term
+    Repeat(Box<Combinator>),
+    /// `seq(s, t, ...)`, applies each in turn, failing if any fails
+    Seq(Vec<Combinator>),
+    /// `choice(s, t, ...)`, the first that succeeds
+    Choice(Vec<Combinator>),
+    /// `oneOf(r, q, ...)`, the same as `choice` but reads better for rules
+    OneOf(Vec<Combinator>),
+    /// `all(s)`, applies `s` to every child of the term
+    All(Box<Combinator>),
+    /// `one(s)`, applies `s` to the first child where it succeeds
+    One(Box<Combinator>),
+    /// `bottomup(s)`, applies `s` to every subterm, children first
+    BottomUp(Box<Combinator>),
+    /// `topdown(s)`, applies `s` to every subterm, parents first
+    TopDown(Box<Combinator>),
+    /// `innermost(s)`, applies `s` bottom up until it applies nowhere
+    Innermost(Box<Combinator>)
+}
+
+impl Combinator {
+
+    /// Every name this strategy calls, which are either named
+    /// strategies or select rules by their label.
+    pub fn calls(&self, out: &mut Vec<SymbolHandle>) {
+        match self {
+            Combinator::Call(name) => out.push(*name),
+            Combinator::Id | Combinator::Fail => {},
+            Combinator::Try(s) | Combinator::Repeat(s) | Combinator::All(s) | Combinator::One(s)
+                | Combinator::BottomUp(s) | Combinator::TopDown(s) | Combinator::Innermost(s) => s.calls(out),
+            Combinator::Seq(list) | Combinator::Choice(list) | Combinator::OneOf(list) => list.iter().for_each(|s| s.calls(out))
+        }
+    }
+
+}
+
+/// True if calling `name` selects rules labeled `label`, `name` may end
+/// in `*` to select every label with that prefix.
+pub fn selects(name: SymbolHandle, label: Option<SymbolHandle>, symbols: &SymbolTable) -> bool {
+    match symbols.lookup(name).strip_suffix('*') {
+        Some(prefix) => label.is_some_and(|l| symbols.lookup(l).starts_with(prefix)),
+        None => label == Some(name)
+    }
+}
+
+/// Applies strategies to terms.
+pub struct Interpreter<'a> {
+    rules: &'a RewriteRules,
+    strategies: &'a HashMap<SymbolHandle, Combinator>,
+    symbols: &'a SymbolTable,
+    max_steps: usize,
+    depth: usize,
+    /// How many rules were applied so far
+    pub steps: usize,
+    /// Set when the step limit or `MAX_DEPTH` was reached,
+    /// every strategy fails from then on.
+    pub exhausted: bool
+}
+
+impl<'a> Interpreter<'a> {
+
+    pub fn new(rules: &'a RewriteRules, strategies: &'a HashMap<SymbolHandle, Combinator>, symbols: &'a SymbolTable, max_steps: usize) -> Self {
+        Interpreter { rules, strategies, symbols, max_steps, depth: 0, steps: 0, exhausted: false }
+    }
+
+    /// The term `strategy` turns `term` into, or None if it fails.
+    pub fn apply(&mut self, strategy: &Combinator, term: &Expression) -> Option<Expression> {
+        if self.exhausted {
+            return None;
+        }
+        if self.depth >= MAX_DEPTH {
+            self.exhausted = true;
+            return None;
+        }
+        self.depth += 1;
+        let result = self.eval(strategy, term);
+        self.depth -= 1;
+        // Failing because of the limits must not look like an ordinary failure to `try`
+        if self.exhausted { None } else { result }
+    }
+
+    fn eval(&mut self, strategy: &Combinator, term: &Expression) -> Option<Expression> {
+        match strategy {
+            Combinator::Call(name) => match self.strategies.get(name) {
+                Some(named) => self.apply(named, term),
+                None => self.rewrite(*name, term)
+            },
+            Combinator::Id => Some(term.clone()),
+            Combinator::Fail => None,
+            Combinator::Try(s) => self.apply(s, term).or_else(|| Some(term.clone())),
+            Combinator::Repeat(s) => {
+                let mut current = term.clone();
+                while let Some(next) = self.apply(s, &current) {
+                    if next == current {
+                        break;
+                    }
+                    current = next;
+                }
+                Some(current)
+            },
+            Combinator::Seq(list) => {
+                let mut current = term.clone();
+                for s in list {
+                    current = self.apply(s, &current)?;
+                }
+                Some(current)
+            },
+            Combinator::Choice(list) | Combinator::OneOf(list) => list.iter().find_map(|s| self.apply(s, term)),
+            Combinator::All(s) => self.children(s, term, true),
+            Combinator::One(s) => self.children(s, term, false),
+            Combinator::BottomUp(s) => {
+                let term = self.children(strategy, term, true)?;
+                self.apply(s, &term)
+            },
+            Combinator::TopDown(s) => {
+                let term = self.apply(s, term)?;
+                self.children(strategy, &term, true)
+            },
+            Combinator::Innermost(s) => {
+                let term = self.children(strategy, term, true)?;
+                match self.apply(s, &term) {
+                    Some(next) => self.apply(strategy, &next),
+                    None => Some(term)
+                }
+            }
+        }
+    }
+
+    /// Apply the first rule selected by `label` that matches the whole term.
+    fn rewrite(&mut self, label: SymbolHandle, term: &Expression) -> Option<Expression> {
+        let rule = self.rules.iter()
+            .find(|rule| selects(label, rule.label, self.symbols) && rule.bindings(term, self.symbols).is_some())?;
+        if self.steps >= self.max_steps {
+            self.exhausted = true;
+            return None;
+        }
+        self.steps += 1;
+        let bindings = rule.bindings(term, self.symbols)?;
+        Some(rule.right.interpolate(&bindings))
+    }
+
+    /// Apply `s` to every child of `term`, or only to the first child it
+    /// succeeds on. The children are the same as for rewriting: each term
+    /// of a compound expression, and the body of a binder.
+    fn children(&mut self, s: &Combinator, term: &Expression, every: bool) -> Option<Expression> {
+        match term.0.as_slice() {
+            [Terminal::Parentheses(e)] => self.children(s, e, every),
+            [Terminal::Binder(hint, body)] => {
+                let body = self.apply(s, &body_expression(body))?;
+                Some(Expression(vec![Terminal::Binder(*hint, Box::new(expression_body(body)))]))
+            },
+            [_] | [] => if every { Some(term.clone()) } else { None },
+            terms => {
+                let mut rewritten = term.clone();
+                let mut changed = false;
+                for (idx, t) in terms.iter().enumerate() {
+                    let child = match t {
+                        Terminal::Parentheses(e) => e.clone(),
+                        _ => Expression(vec![t.clone()])
+                    };
+                    match self.apply(s, &child) {
+                        Some(result) => {
+                            rewritten.0[idx] = expression_body(result);
+                            if !every {
+                                changed = true;
+                                break;
+                            }
+                        },
+                        None if every => return None,
+                        None => {}
+                    }
+                }
+                if every || changed { Some(rewritten) } else { None }
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::parse::TryParse;
+    use crate::reduce::RewriteRule;
+
+    #[test]
+    fn test_bottomup() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let one = symbols.handle("define-one");
+        rules.add(RewriteRule::labeled(Some(one), expr!(symbols 1), expr!(symbols S 0)));
+        let strategies = HashMap::new();
+        let strategy = Combinator::parse("bottomup(try(define-one))", &mut symbols).unwrap();
+        let term = expr!(symbols 1 + (1 + 2));
+        let expected = expr!(symbols (S 0) + ((S 0) + 2));
+        let mut interpreter = Interpreter::new(&rules, &strategies, &symbols, 100);
+        assert_eq!(interpreter.apply(&strategy, &term), Some(expected));
+        assert_eq!(interpreter.steps, 2);
+    }
+
+    #[test]
+    fn test_label_prefix() {
+        let mut symbols = SymbolTable::new();
+        let mut rules = RewriteRules::new();
+        let add_0 = symbols.handle("add-0");
+        let add_1 = symbols.handle("add-1");
+        rules.add(RewriteRule::labeled(Some(add_0), expr!(symbols [x] + 0), expr!(symbols [x])));
+        rules.add(RewriteRule::labeled(Some(add_1), expr!(symbols [x] + (S [y])), expr!(symbols S ([x] + [y]))));
+        let strategies = HashMap::new();
+        let innermost = Combinator::parse("innermost(oneOf(add-*))", &mut symbols).unwrap();
+        let single = Combinator::parse("add-0", &mut symbols).unwrap();
+        let term = expr!(symbols a + (S (S 0)));
+        let expected = expr!(symbols S (S a));
+        let mut interpreter = Interpreter::new(&rules, &strategies, &symbols, 100);
+        assert_eq!(interpreter.apply(&innermost, &term), Some(expected));
+        // A single rule only applies to the whole term
+        assert_eq!(interpreter.apply(&single, &term), None);
+    }
+
+}
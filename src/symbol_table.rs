@@ -1,11 +1,15 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
+use regex::Regex;
 
+#[derive(Default, Clone)]
 pub struct SymbolTable {
-    symbols: Vec<String>
+    symbols: Vec<String>,
+    // Compiled once for every regex guard parsed with this table
+    regexes: HashMap<String, Regex>
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct SymbolHandle {
     idx: usize,
 }
@@ -22,7 +26,8 @@ impl SymbolTable {
 
     pub fn new() -> Self {
         SymbolTable {
-            symbols: Vec::new()
+            symbols: Vec::new(),
+            regexes: HashMap::new()
         }
     }
 
@@ -36,10 +41,71 @@ impl SymbolTable {
         SymbolHandle { idx: self.symbols.len()-1 }
     }
 
+    /// The handle of `s` if it has been interned before.
+    pub fn find(&self, s: &str) -> Option<SymbolHandle> {
+        self.symbols.iter().position(|symbol| symbol == s).map(|idx| SymbolHandle { idx })
+    }
+
     pub fn lookup(&self, handle: SymbolHandle) -> &str {
         &self.symbols[handle.idx]
     }
 
+    /// A pattern for names matching the regex `source` as a whole.
+    pub fn regex(&mut self, source: &str) -> Result<NamePattern, regex::Error> {
+        if !self.regexes.contains_key(source) {
+            self.regexes.insert(source.to_owned(), compile(source)?);
+        }
+        Ok(NamePattern::Regex(source.to_owned()))
+    }
+
+    /// True if the name of `handle` matches `pattern`.
+    pub fn accepts(&self, pattern: &NamePattern, handle: SymbolHandle) -> bool {
+        let name = self.lookup(handle);
+        match pattern {
+            NamePattern::Integer => name.parse::<i64>().is_ok(),
+            NamePattern::Regex(source) => match self.regexes.get(source) {
+                Some(regex) => regex.is_match(name),
+                // Not parsed with this table, such as a guard built in code
+                None => compile(source).is_ok_and(|regex| regex.is_match(name))
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.symbols.iter().map(|s| s.as_str())
+    }
+
+}
+
+/// A property of symbol names, checked by guards like `$x:int`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NamePattern {
+    Integer,
+    /// Matches the whole name, holds the regex as written
+    Regex(String)
+}
+
+impl NamePattern {
+
+    /// A pattern for names matching `source` as a whole.
+    pub fn regex(source: &str) -> Result<Self, regex::Error> {
+        compile(source)?;
+        Ok(NamePattern::Regex(source.to_owned()))
+    }
+
+}
+
+fn compile(source: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", source))
+}
+
+impl Display for NamePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NamePattern::Integer => write!(f, "int"),
+            NamePattern::Regex(source) => write!(f, "/{}/", source)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -63,4 +129,18 @@ mod tests {
         assert_eq!(handle1, handle2);
     }
 
+    #[test]
+    fn test_name_pattern() {
+        let mut symbols = SymbolTable::new();
+        let lower = symbols.regex("[a-z]+").unwrap();
+        let ten = symbols.handle("10");
+        let minus_two = symbols.handle("-2");
+        let abc = symbols.handle("abc");
+        assert!(symbols.accepts(&NamePattern::Integer, ten));
+        assert!(symbols.accepts(&NamePattern::Integer, minus_two));
+        assert!(!symbols.accepts(&NamePattern::Integer, abc));
+        assert!(symbols.accepts(&lower, abc));
+        assert!(!symbols.accepts(&lower, ten));
+    }
+
 }
@@ -0,0 +1,286 @@
+use std::{error::Error, fmt::Display};
+
+use crate::expr::*;
+use crate::parse::{ErrorKind, ParseError, TryParse};
+use crate::reduce::{Derivation, Rewrite, RewriteRule, RewriteRules};
+use crate::symbol_table::SymbolTable;
+use crate::lint::{self, Lint};
+
+/// A rule set together with the symbols it was parsed with.
+/// This is the engine behind the REPL-less interfaces, such as the WASM bindings.
+#[derive(Default, Clone)]
+pub struct Trs {
+    symbols: SymbolTable,
+    rules: RewriteRules
+}
+
+/// A line of source that could not be parsed.
+/// Lines and columns start from 0.
+#[derive(Debug)]
+pub struct SourceError {
+    pub line: usize,
+    pub error: ParseError
+}
+
+impl SourceError {
+
+    pub fn column(&self) -> usize {
+        self.error.column()
+    }
+
+}
+
+impl Error for SourceError {}
+
+impl Display for SourceError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Parse error at line {} col {}: {}", self.line, self.error.column(), self.error.kind())
+    }
+
+}
+
+impl Trs {
+
+    pub fn new() -> Self {
+        Trs { symbols: SymbolTable::new(), rules: RewriteRules::new() }
+    }
+
+    /// Parse a rule set, failing if any line could not be parsed.
+    pub fn from_source(src: &str) -> Result<Self, Vec<SourceError>> {
+        let mut trs = Trs::new();
+        trs.load(src)?;
+        Ok(trs)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn rules(&self) -> &RewriteRules {
+        &self.rules
+    }
+
+    pub fn into_parts(self) -> (SymbolTable, RewriteRules) {
+        (self.symbols, self.rules)
+    }
+
+    /// Add the rules defined in `src`. Queries are ignored.
+    /// Lines that fail to parse or define an invalid rule are reported,
+    /// all other lines are still added.
+    pub fn load(&mut self, src: &str) -> Result<(), Vec<SourceError>> {
+        let mut errors = vec![];
+        for (line, text) in src.lines().enumerate() {
+            match self.parse_item(text).and_then(|item| self.define(&item)) {
+                Ok(_) => {},
+                Err(error) => errors.push(SourceError { line, error })
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Parse a single line of a rule file.
+    pub fn parse_item(&mut self, src: &str) -> Result<Item, ParseError> {
+        Item::parse_complete(src, &mut self.symbols)
+    }
+
+    /// Add the rule or `:lazy` declaration of `item`, if it is one, and
+    /// return the lints for the rule, see `lint::define`.
+    /// Rules that fail `validate` are not added.
+    pub fn define(&mut self, item: &Item) -> Result<Vec<Lint>, ParseError> {
+        match &item.statement {
+            Statement::Noop => {},
+            // Queries and assertions are answered by the caller, not stored as rules
+            Statement::Rewrite(_, r, _) if r.is_query(&self.symbols) => {},
+            Statement::Reaches(..) | Statement::NotReaches(..) | Statement::Expect(..) => {},
+            Statement::Proof(_) | Statement::Step(..) | Statement::Qed => {},
+            Statement::Strategy(..) | Statement::Apply(..) => {},
+            Statement::Property(..) | Statement::Signature(..) => {},
+            Statement::Lazy(map) => self.rules.add_lazy(map.clone()),
+            Statement::Rewrite(l, r, constraints) => {
+                let rule = RewriteRule::labeled(item.label.as_ref().map(|label| label.0), l.clone(), r.clone())
+                    .with_constraints(constraints.clone());
+                return lint::define(&mut self.rules, rule, &self.symbols, &[]).map_err(|invalid| {
+                    ParseError::new(0, ErrorKind::InvalidRule(invalid.describe(&self.symbols)))
+                });
+            }
+        }
+        Ok(vec![])
+    }
+
+    /// Remove all rules and `:lazy` declarations, keeping interned symbols.
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    /// Replace all rules with those in `src`, keeping interned symbols.
+    pub fn replace_source(&mut self, src: &str) -> Result<(), Vec<SourceError>> {
+        self.clear();
+        self.load(src)
+    }
+
+    /// Remove every rule labeled `label`, returning how many were removed.
+    pub fn remove_rule(&mut self, label: &str) -> usize {
+        match self.symbols.find(label) {
+            Some(handle) => self.rules.remove_labeled(handle),
+            None => 0
+        }
+    }
+
+    /// Parse a single term, which must make up the whole input.
+    pub fn parse(&mut self, src: &str) -> Result<Expression, ParseError> {
+        Expression::parse_complete(src, &mut self.symbols)
+    }
+
+    /// Reduce `expr` until it reaches a normal form or `max_steps` rewrites were applied.
+    pub fn derive(&mut self, expr: &str, max_steps: usize) -> Result<Derivation, ParseError> {
+        Ok(self.parse(expr)?.derive(&self.rules, &self.symbols, max_steps))
+    }
+
+    /// Every place in `expr` where a rule applies, in the order they would be reduced.
+    pub fn find_redexes(&mut self, expr: &str) -> Result<(Expression, Vec<Rewrite>), ParseError> {
+        let term = self.parse(expr)?;
+        let redexes = self.rules.find_redexes(&term, &self.symbols);
+        Ok((term, redexes))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn normal_form(trs: &mut Trs, term: &str) -> String {
+        let derivation = trs.derive(term, 10).unwrap();
+        derivation.result().bind(trs.symbols()).to_string()
+    }
+
+    #[test]
+    fn test_from_source_errors() {
+        let errors = Trs::from_source("1 -> S 0\nnot a rule\n2 -> S 1").err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for src in ["f $x -> $y", "g $$x -> $$x", "h $x $$x -> a", "k $b -> $b[$y]", "f $x -> a where $y != $x"] {
+            assert!(Trs::from_source(src).is_err(), "{}", src);
+        }
+        // Matching every term is fine for a rule a strategy applies by label
+        assert!(Trs::from_source("f $x -> $x\n$x -> ?\n[beta] (\\x. $b) $a -> $b[$a]\n[wrap] $x -> w $x").is_ok());
+    }
+
+    #[test]
+    fn test_keyword_rules() {
+        // Rules whose left side starts with a keyword, not statements
+        let mut trs = Trs::from_source("qed -> done\nproof a -> b\nsignature x -> y").unwrap();
+        assert_eq!(trs.rules().len(), 3);
+        assert_eq!(normal_form(&mut trs, "qed"), "done");
+        assert_eq!(normal_form(&mut trs, "proof a"), "b");
+        assert_eq!(normal_form(&mut trs, "signature x"), "y");
+    }
+
+    #[test]
+    fn test_delimiter_symbols() {
+        // Commas and braces end a symbol only in constraints, signatures and proof steps
+        let mut trs = Trs::from_source("x , y -> z\na in {b} -> c\nf $x -> a, where $x != b, $x != c").unwrap();
+        assert_eq!(normal_form(&mut trs, "x , y"), "z");
+        assert_eq!(normal_form(&mut trs, "a in {b}"), "c");
+        assert_eq!(normal_form(&mut trs, "f d"), "a,");
+        assert_eq!(normal_form(&mut trs, "f c"), "f c");
+    }
+
+    #[test]
+    fn test_bracket_symbols() {
+        // `]` only ends the argument of a substitution
+        let mut trs = Trs::from_source("a]b -> c\n(\\x. $b) $y -> $b[$y]").unwrap();
+        assert_eq!(normal_form(&mut trs, "a]b"), "c");
+        assert_eq!(normal_form(&mut trs, "(\\x. g x) a]b"), "g c");
+    }
+
+    #[test]
+    fn test_derive() {
+        let mut trs = Trs::from_source("[define-one] 1 -> S 0\n$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)\n1 + 1 -> ?").unwrap();
+        assert_eq!(trs.rules().len(), 3);
+        let derivation = trs.derive("1 + 1", 100).unwrap();
+        assert_eq!(derivation.result().bind(trs.symbols()).to_string(), "S (S 0)");
+        assert!(!derivation.truncated);
+    }
+
+    #[test]
+    fn test_constraints() {
+        let mut trs = Trs::from_source("eq $x $y -> no where $x != $y\npos $x -> yes where $x !~ 0, $x !~ (neg $z)\nfirst $$y $x -> distinct").unwrap();
+        assert_eq!(normal_form(&mut trs, "eq a b"), "no");
+        assert_eq!(normal_form(&mut trs, "eq a a"), "eq a a");
+        assert_eq!(normal_form(&mut trs, "pos 1"), "yes");
+        assert_eq!(normal_form(&mut trs, "pos 0"), "pos 0");
+        assert_eq!(normal_form(&mut trs, "pos (neg 1)"), "pos (neg 1)");
+        // A distinct variable differs from the others whichever is bound first
+        assert_eq!(normal_form(&mut trs, "first a a"), "first a a");
+        assert_eq!(normal_form(&mut trs, "first a b"), "distinct");
+    }
+
+    #[test]
+    fn test_distinct_equality() {
+        // How equality was defined before `where` clauses existed
+        let mut trs = Trs::from_source("[define-equals-1] $x = $$y -> false\n[flipped] $$y == $x -> false").unwrap();
+        assert_eq!(normal_form(&mut trs, "a = b"), "false");
+        assert_eq!(normal_form(&mut trs, "a = a"), "a = a");
+        assert_eq!(normal_form(&mut trs, "(S 0) = (S 0)"), "(S 0) = (S 0)");
+        assert_eq!(normal_form(&mut trs, "(S 0) = 0"), "false");
+        // Used to match, the distinct variable was only compared to those bound before it
+        assert_eq!(normal_form(&mut trs, "a == a"), "a == a");
+        assert_eq!(normal_form(&mut trs, "a == b"), "false");
+    }
+
+    #[test]
+    fn test_canonical_parens() {
+        let mut trs = Trs::from_source("f (a) -> matched\nwrap $x -> ($x)\nsame $x $x -> yes").unwrap();
+        assert_eq!(normal_form(&mut trs, "f a"), "matched");
+        assert_eq!(normal_form(&mut trs, "f ((a))"), "matched");
+        assert_eq!(normal_form(&mut trs, "((g (b)))"), "g b");
+        // Rewriting does not leave parentheses around a single term
+        assert_eq!(normal_form(&mut trs, "same (wrap a) a"), "yes");
+        assert_eq!(normal_form(&mut trs, "same (wrap (a b)) (a b)"), "yes");
+    }
+
+    #[test]
+    fn test_lazy() {
+        let src = "loop -> loop\nnot true -> false\nif true then $x else $y -> $x\nif false then $x else $y -> $y\n:lazy if _ then _ else _";
+        let mut trs = Trs::from_source(src).unwrap();
+        let derivation = trs.derive("if (not true) then loop else 0", 10).unwrap();
+        assert_eq!(derivation.result().bind(trs.symbols()).to_string(), "0");
+        assert!(!derivation.truncated);
+        // Only the condition is a redex, not the loop in the branch
+        let (_, redexes) = trs.find_redexes("if (not true) then loop else loop").unwrap();
+        assert_eq!(redexes.len(), 1);
+    }
+
+    #[test]
+    fn test_guards() {
+        let mut trs = Trs::from_source("kind $x:int -> number\nkind $x in {a, b} -> letter\nkind $x:paren -> compound\nkind $x:sym -> other").unwrap();
+        // The integer guard also knows symbols interned after the rule
+        assert_eq!(normal_form(&mut trs, "kind 42"), "number");
+        assert_eq!(normal_form(&mut trs, "kind b"), "letter");
+        assert_eq!(normal_form(&mut trs, "kind (S 0)"), "compound");
+        assert_eq!(normal_form(&mut trs, "kind c"), "other");
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Trs>();
+    }
+
+    #[test]
+    fn test_guards_after_clone() {
+        let mut trs = Trs::from_source("kind $x:/[a-z]+/ -> word\nkind $x -> other").unwrap();
+        let mut copy = trs.clone();
+        // Both copies give the next new symbol the same handle
+        copy.derive("kind abc", 10).unwrap();
+        assert_eq!(normal_form(&mut trs, "kind 42"), "other");
+    }
+
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use crate::{expr::*, symbol_table::SymbolTable};
+use crate::{binder::{body_expression, mentions}, expr::*, reduce::{ReplacementMap, Slot}, signature::{Shape, Signature}, strategy::Combinator, symbol_table::{SymbolHandle, SymbolTable}};
 
 impl Display for Comment {
 
@@ -71,7 +71,7 @@ impl<'s> Display for BoundItem<'s> {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(label) = &self.item.label {
-            write!(f, "{} ", label.bind(&self.symbols))?;
+            write!(f, "{} ", label.bind(self.symbols))?;
         }
         write!(f, "{}", self.item.statement.bind(self.symbols))?;
         if let Some(comment) = &self.item.comment {
@@ -90,7 +90,35 @@ impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Statement::Noop => write!(f, ""),
-            Statement::Rewrite(l, r) => write!(f, "{} -> {}", l, r)
+            Statement::Rewrite(l, r, constraints) => {
+                write!(f, "{} -> {}", l, r)?;
+                for (idx, c) in constraints.iter().enumerate() {
+                    write!(f, "{}{}", if idx == 0 { " where " } else { ", " }, c)?;
+                }
+                Ok(())
+            },
+            Statement::Reaches(l, r) => write!(f, "assert {} ->* {}", l, r),
+            Statement::NotReaches(l, r) => write!(f, "assert {} -/->* {}", l, r),
+            Statement::Expect(l, r) => write!(f, "expect {} -> ? {}", l, r),
+            Statement::Proof(e) => write!(f, "proof {}", e),
+            Statement::Step(e, label) => write!(f, "= {} {{by {}}}", e, label),
+            Statement::Qed => write!(f, "qed"),
+            Statement::Strategy(name, s) => write!(f, "strategy {} = {}", name, s),
+            Statement::Apply(s, e) => write!(f, "apply {}: {}", s, e),
+            Statement::Lazy(map) => write!(f, ":lazy {}", map),
+            Statement::Property(vars, body) => {
+                write!(f, "forall")?;
+                for v in vars {
+                    write!(f, " ${}", v)?;
+                }
+                match body.as_ref() {
+                    Statement::Reaches(l, r) => write!(f, ": {} ->* {}", l, r),
+                    Statement::NotReaches(l, r) => write!(f, ": {} -/->* {}", l, r),
+                    Statement::Expect(l, r) => write!(f, ": {} -> ? {}", l, r),
+                    other => write!(f, ": {}", other)
+                }
+            },
+            Statement::Signature(signature) => write!(f, "signature {}", signature)
         }
     }
 
@@ -115,12 +143,251 @@ impl<'s> Display for BoundStatement<'s> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.statement {
             Statement::Noop => write!(f, ""),
-            Statement::Rewrite(l, r) => write!(f, "{} -> {}", l.bind(self.symbols), r.bind(self.symbols))
+            Statement::Rewrite(l, r, constraints) => write!(
+                f,
+                "{} -> {}{}",
+                l.bind(self.symbols),
+                r.bind(self.symbols),
+                where_clause(constraints, self.symbols)
+            ),
+            Statement::Reaches(l, r) => write!(f, "assert {} ->* {}", l.bind(self.symbols), r.bind(self.symbols)),
+            Statement::NotReaches(l, r) => write!(f, "assert {} -/->* {}", l.bind(self.symbols), r.bind(self.symbols)),
+            Statement::Expect(l, r) => write!(f, "expect {} -> ? {}", l.bind(self.symbols), r.bind(self.symbols)),
+            Statement::Proof(e) => write!(f, "proof {}", e.bind(self.symbols)),
+            Statement::Step(e, label) => write!(f, "= {} {{by {}}}", e.bind(self.symbols), self.symbols.lookup(*label)),
+            Statement::Qed => write!(f, "qed"),
+            Statement::Strategy(name, s) => write!(f, "strategy {} = {}", self.symbols.lookup(*name), s.bind(self.symbols)),
+            Statement::Apply(s, e) => write!(f, "apply {}: {}", s.bind(self.symbols), e.bind(self.symbols)),
+            Statement::Lazy(map) => write!(f, ":lazy {}", map.bind(self.symbols)),
+            Statement::Property(vars, body) => {
+                write!(f, "forall")?;
+                for v in vars {
+                    write!(f, " ${}", self.symbols.lookup(*v))?;
+                }
+                match body.as_ref() {
+                    Statement::Reaches(l, r) => write!(f, ": {} ->* {}", l.bind(self.symbols), r.bind(self.symbols)),
+                    Statement::NotReaches(l, r) => write!(f, ": {} -/->* {}", l.bind(self.symbols), r.bind(self.symbols)),
+                    Statement::Expect(l, r) => write!(f, ": {} -> ? {}", l.bind(self.symbols), r.bind(self.symbols)),
+                    other => write!(f, ": {}", other.bind(self.symbols))
+                }
+            },
+            Statement::Signature(signature) => write!(f, "signature {}", signature.bind(self.symbols))
         }
     }
 
 }
 
+fn write_combinator(f: &mut std::fmt::Formatter<'_>, c: &Combinator, name: &dyn Fn(SymbolHandle) -> String) -> std::fmt::Result {
+    let (keyword, arguments) = match c {
+        Combinator::Call(handle) => return write!(f, "{}", name(*handle)),
+        Combinator::Id => return write!(f, "id"),
+        Combinator::Fail => return write!(f, "fail"),
+        Combinator::Try(s) => ("try", std::slice::from_ref(s.as_ref())),
+        Combinator::Repeat(s) => ("repeat", std::slice::from_ref(s.as_ref())),
+        Combinator::Seq(list) => ("seq", list.as_slice()),
+        Combinator::Choice(list) => ("choice", list.as_slice()),
+        Combinator::OneOf(list) => ("oneOf", list.as_slice()),
+        Combinator::All(s) => ("all", std::slice::from_ref(s.as_ref())),
+        Combinator::One(s) => ("one", std::slice::from_ref(s.as_ref())),
+        Combinator::BottomUp(s) => ("bottomup", std::slice::from_ref(s.as_ref())),
+        Combinator::TopDown(s) => ("topdown", std::slice::from_ref(s.as_ref())),
+        Combinator::Innermost(s) => ("innermost", std::slice::from_ref(s.as_ref()))
+    };
+    write!(f, "{}(", keyword)?;
+    for (idx, argument) in arguments.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        write_combinator(f, argument, name)?;
+    }
+    write!(f, ")")
+}
+
+impl Display for Combinator {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_combinator(f, self, &|handle| handle.to_string())
+    }
+
+}
+
+pub struct BoundCombinator<'s> {
+    symbols: &'s SymbolTable,
+    combinator: &'s Combinator
+}
+
+impl Combinator {
+
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundCombinator<'s> {
+        BoundCombinator { symbols, combinator: self }
+    }
+
+}
+
+impl<'s> Display for BoundCombinator<'s> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_combinator(f, self.combinator, &|handle| self.symbols.lookup(handle).to_owned())
+    }
+
+}
+
+fn write_slots(f: &mut std::fmt::Formatter<'_>, map: &ReplacementMap, name: &dyn Fn(SymbolHandle) -> String) -> std::fmt::Result {
+    for (idx, slot) in map.0.iter().enumerate() {
+        if idx > 0 {
+            write!(f, " ")?;
+        }
+        match slot {
+            Slot::Symbol(s) => write!(f, "{}", name(*s))?,
+            Slot::Active => write!(f, "!")?,
+            Slot::Frozen => write!(f, "_")?
+        }
+    }
+    Ok(())
+}
+
+impl Display for ReplacementMap {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_slots(f, self, &|handle| handle.to_string())
+    }
+
+}
+
+pub struct BoundReplacementMap<'s> {
+    symbols: &'s SymbolTable,
+    map: &'s ReplacementMap
+}
+
+impl ReplacementMap {
+
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundReplacementMap<'s> {
+        BoundReplacementMap { symbols, map: self }
+    }
+
+}
+
+impl<'s> Display for BoundReplacementMap<'s> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_slots(f, self.map, &|handle| self.symbols.lookup(handle).to_owned())
+    }
+
+}
+
+fn write_shape(f: &mut std::fmt::Formatter<'_>, shape: &Shape, name: &dyn Fn(SymbolHandle) -> String) -> std::fmt::Result {
+    for (idx, slot) in shape.0.iter().enumerate() {
+        if idx > 0 {
+            write!(f, " ")?;
+        }
+        match slot {
+            Some(s) => write!(f, "{}", name(*s))?,
+            None => write!(f, "_")?
+        }
+    }
+    Ok(())
+}
+
+fn write_signature(f: &mut std::fmt::Formatter<'_>, signature: &Signature, name: &dyn Fn(SymbolHandle) -> String) -> std::fmt::Result {
+    for (idx, constant) in signature.constants.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", name(*constant))?;
+    }
+    for (idx, shape) in signature.shapes.iter().enumerate() {
+        if idx > 0 || !signature.constants.is_empty() {
+            write!(f, ", ")?;
+        }
+        write_shape(f, shape, name)?;
+    }
+    Ok(())
+}
+
+impl Display for Shape {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_shape(f, self, &|handle| handle.to_string())
+    }
+
+}
+
+pub struct BoundShape<'s> {
+    symbols: &'s SymbolTable,
+    shape: &'s Shape
+}
+
+impl Shape {
+
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundShape<'s> {
+        BoundShape { symbols, shape: self }
+    }
+
+}
+
+impl<'s> Display for BoundShape<'s> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_shape(f, self.shape, &|handle| self.symbols.lookup(handle).to_owned())
+    }
+
+}
+
+impl Display for Signature {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_signature(f, self, &|handle| handle.to_string())
+    }
+
+}
+
+pub struct BoundSignature<'s> {
+    symbols: &'s SymbolTable,
+    signature: &'s Signature
+}
+
+impl Signature {
+
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundSignature<'s> {
+        BoundSignature { symbols, signature: self }
+    }
+
+}
+
+impl<'s> Display for BoundSignature<'s> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_signature(f, self.signature, &|handle| self.symbols.lookup(handle).to_owned())
+    }
+
+}
+
+impl Display for Constraint {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::NotEqual(a, b) => write!(f, "{} != {}", a, b),
+            Constraint::NotMatching(a, b) => write!(f, "{} !~ {}", a, b)
+        }
+    }
+
+}
+
+/// ` where $x != $y, ...` for a rule with constraints, empty otherwise.
+pub fn where_clause(constraints: &[Constraint], symbols: &SymbolTable) -> String {
+    let constraints: Vec<String> = constraints.iter()
+        .map(|c| match c {
+            Constraint::NotEqual(a, b) => format!("{} != {}", a.bind(symbols), b.bind(symbols)),
+            Constraint::NotMatching(a, b) => format!("{} !~ {}", a.bind(symbols), b.bind(symbols))
+        })
+        .collect();
+    if constraints.is_empty() {
+        String::new()
+    } else {
+        format!(" where {}", constraints.join(", "))
+    }
+}
+
 impl Display for Expression {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -152,17 +419,85 @@ impl Expression {
 impl<'s> Display for BoundExpression<'s> {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut first = true;
-        for term in &self.expr.0 {
-            if !first {
-                write!(f, " ")?;
-            }
-            write!(f, "{}", term.bind(self.symbols))?;
-            first = false;
+        write_expression(f, self.symbols, &self.expr.0, &mut vec![])
+    }
+
+}
+
+/// Write `terms` under binders whose names are `scope`, innermost last.
+fn write_expression(f: &mut std::fmt::Formatter<'_>, symbols: &SymbolTable, terms: &[Terminal], scope: &mut Vec<String>) -> std::fmt::Result {
+    for (idx, term) in terms.iter().enumerate() {
+        if idx > 0 {
+            write!(f, " ")?;
+        }
+        // A binder extends as far to the right as possible
+        if matches!(term, Terminal::Binder(..)) && idx + 1 < terms.len() {
+            write!(f, "(")?;
+            write_terminal(f, symbols, term, scope)?;
+            write!(f, ")")?;
+        } else {
+            write_terminal(f, symbols, term, scope)?;
         }
-        Ok(())
     }
+    Ok(())
+}
+
+/// True if `term`, the body of a binder, refers to the variable
+/// bound `outer` binders outside of that binder.
+fn refers_to(term: &Terminal, outer: usize, depth: usize) -> bool {
+    match term {
+        Terminal::Bound(idx) => *idx == depth + 1 + outer,
+        Terminal::Binder(_, body) => refers_to(body, outer, depth + 1),
+        Terminal::Parentheses(e) => e.0.iter().any(|t| refers_to(t, outer, depth)),
+        Terminal::Substitution(_, arg) => refers_to(arg, outer, depth),
+        _ => false
+    }
+}
 
+fn write_terminal(f: &mut std::fmt::Formatter<'_>, symbols: &SymbolTable, term: &Terminal, scope: &mut Vec<String>) -> std::fmt::Result {
+    match term {
+        Terminal::Parentheses(e) => {
+            write!(f, "(")?;
+            write_expression(f, symbols, &e.0, scope)?;
+            write!(f, ")")
+        },
+        Terminal::Symbol(s) => write!(f, "{}", symbols.lookup(*s)),
+        Terminal::Variable(v, k, guard) => {
+            write!(f, "{}{}", k, symbols.lookup(*v))?;
+            match guard.as_deref() {
+                Some(Guard::OneOf(members)) => {
+                    let names: Vec<&str> = members.iter().map(|m| symbols.lookup(*m)).collect();
+                    write!(f, " in {{{}}}", names.join(", "))
+                },
+                Some(guard) => write!(f, "{}", guard),
+                None => Ok(())
+            }
+        },
+        Terminal::Binder(hint, body) => {
+            // Rename the variable if it would capture a symbol of the same
+            // name, or shadow an enclosing variable that the body refers to
+            let mut name = symbols.lookup(hint.0).to_owned();
+            while mentions(body, &name, symbols)
+                || scope.iter().rev().enumerate().any(|(outer, n)| *n == name && refers_to(body, outer, 0)) {
+                name.push('\'');
+            }
+            write!(f, "\\{}. ", name)?;
+            scope.push(name);
+            let result = write_expression(f, symbols, &body_expression(body).0, scope);
+            scope.pop();
+            result
+        },
+        Terminal::Bound(idx) => match scope.len().checked_sub(idx + 1) {
+            Some(level) => write!(f, "{}", scope[level]),
+            // Not under as many binders as the index refers to
+            None => write!(f, "#{}", idx)
+        },
+        Terminal::Substitution(v, arg) => {
+            write!(f, "${}[", symbols.lookup(*v))?;
+            write_terminal(f, symbols, arg, scope)?;
+            write!(f, "]")
+        }
+    }
 }
 
 impl Display for Terminal {
@@ -171,12 +506,30 @@ impl Display for Terminal {
         match self {
             Terminal::Parentheses(e) => write!(f, "({})", e),
             Terminal::Symbol(s) => write!(f, "{}", s),
-            Terminal::Variable(v, k) => write!(f, "{}{}", k, v)
+            Terminal::Variable(v, k, None) => write!(f, "{}{}", k, v),
+            Terminal::Variable(v, k, Some(guard)) => write!(f, "{}{}{}", k, v, guard),
+            Terminal::Binder(hint, body) => write!(f, "\\{}. {}", hint.0, body_expression(body)),
+            Terminal::Bound(idx) => write!(f, "#{}", idx),
+            Terminal::Substitution(v, arg) => write!(f, "${}[{}]", v, arg)
         }
     }
 
 }
 
+impl Display for Guard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Guard::Symbol => write!(f, ":sym"),
+            Guard::Compound => write!(f, ":paren"),
+            Guard::Name(pattern) => write!(f, ":{}", pattern),
+            Guard::OneOf(members) => {
+                let members: Vec<String> = members.iter().map(|m| m.to_string()).collect();
+                write!(f, " in {{{}}}", members.join(", "))
+            }
+        }
+    }
+}
+
 impl Display for VariableKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -188,7 +541,7 @@ impl Display for VariableKind {
 
 impl Terminal {
 
-    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundTerminal {
+    pub fn bind<'s>(&'s self, symbols: &'s SymbolTable) -> BoundTerminal<'s> {
         BoundTerminal { symbols, terminal: self }
     }
 
@@ -202,10 +555,6 @@ pub struct BoundTerminal<'s> {
 impl<'s> Display for BoundTerminal<'s> {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.terminal {
-            Terminal::Parentheses(e) => write!(f, "({})", e.bind(self.symbols)),
-            Terminal::Symbol(s) => write!(f, "{}", self.symbols.lookup(*s)),
-            Terminal::Variable(v, k) => write!(f, "{}{}", k, self.symbols.lookup(*v))
-        }
+        write_terminal(f, self.symbols, self.terminal, &mut vec![])
     }
 }
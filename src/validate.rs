@@ -0,0 +1,115 @@
+//! Rules that parse but can't do what they look like they do.
+//!
+//! These are rejected when a rule is defined, unlike the rules `lint`
+//! warns about, which are valid but never fire.
+
+use crate::expr::*;
+use crate::reduce::RewriteRule;
+use crate::symbol_table::{SymbolHandle, SymbolTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidRule {
+    /// A variable on the right side or in a constraint that the left side does not bind
+    UnboundVariable(SymbolHandle),
+    /// `$$x` on the right side, where it means the same as `$x`
+    DistinctOnRight(SymbolHandle),
+    /// `$x` and `$$x` in the same left side
+    MixedVariableKinds(SymbolHandle)
+}
+
+impl InvalidRule {
+
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        match *self {
+            InvalidRule::UnboundVariable(v) => format!("${} is not bound by the left side", symbols.lookup(v)),
+            InvalidRule::DistinctOnRight(v) => format!("$${} on the right side, distinct variables belong on the left side", symbols.lookup(v)),
+            InvalidRule::MixedVariableKinds(v) => format!("${} and $${} are the same variable, use one of them", symbols.lookup(v), symbols.lookup(v))
+        }
+    }
+
+}
+
+/// The variables in `terms` with their kinds, in order of appearance.
+fn variables(terms: &[Terminal], out: &mut Vec<(SymbolHandle, VariableKind)>) {
+    for term in terms {
+        match term {
+            Terminal::Variable(v, kind, _) => out.push((*v, *kind)),
+            Terminal::Substitution(v, arg) => {
+                out.push((*v, VariableKind::Any));
+                variables(std::slice::from_ref(arg.as_ref()), out);
+            },
+            Terminal::Parentheses(e) => variables(&e.0, out),
+            Terminal::Binder(_, body) => variables(std::slice::from_ref(body.as_ref()), out),
+            _ => {}
+        }
+    }
+}
+
+/// Check that every variable `rule` uses is bound by its left side, and used
+/// the same way there. Variables in the pattern of a `!~` constraint that the
+/// left side does not bind are fine, they match any term.
+pub fn validate(rule: &RewriteRule) -> Result<(), InvalidRule> {
+    let mut bound = vec![];
+    variables(&rule.left.0, &mut bound);
+    for (v, kind) in &bound {
+        if bound.iter().any(|(w, other)| w == v && other != kind) {
+            return Err(InvalidRule::MixedVariableKinds(*v));
+        }
+    }
+    let mut used = vec![];
+    variables(&rule.right.0, &mut used);
+    for (v, kind) in &used {
+        if *kind == VariableKind::Distinct {
+            return Err(InvalidRule::DistinctOnRight(*v));
+        }
+    }
+    for constraint in &rule.constraints {
+        match constraint {
+            Constraint::NotEqual(a, b) => variables(&[a.clone(), b.clone()], &mut used),
+            Constraint::NotMatching(term, _) => variables(std::slice::from_ref(term), &mut used)
+        }
+    }
+    match used.into_iter().find(|(v, _)| !bound.iter().any(|(w, _)| w == v)) {
+        Some((v, _)) => Err(InvalidRule::UnboundVariable(v)),
+        None => Ok(())
+    }
+}
+
+/// True if the left side of `rule` is a lone variable without a guard, so it
+/// matches every term. That is only useful when a strategy applies the rule
+/// by its label, anywhere else it rewrites every term it sees.
+pub fn matches_everything(rule: &RewriteRule) -> bool {
+    matches!(rule.left.0.as_slice(), [Terminal::Variable(_, _, None)])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::parse::TryParse;
+
+    fn rule(src: &str, symbols: &mut SymbolTable) -> RewriteRule {
+        match Item::parse_complete(src, symbols).unwrap().statement {
+            Statement::Rewrite(l, r, constraints) => RewriteRule::new(l, r).with_constraints(constraints),
+            _ => panic!("not a rule: {}", src)
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut symbols = SymbolTable::new();
+        let x = symbols.handle("x");
+        let y = symbols.handle("y");
+        assert_eq!(validate(&rule("f $x -> $y", &mut symbols)), Err(InvalidRule::UnboundVariable(y)));
+        assert_eq!(validate(&rule("f $x -> a where $y != $x", &mut symbols)), Err(InvalidRule::UnboundVariable(y)));
+        assert_eq!(validate(&rule("f $x -> a where $y !~ 0", &mut symbols)), Err(InvalidRule::UnboundVariable(y)));
+        assert_eq!(validate(&rule("g $$x -> $$x", &mut symbols)), Err(InvalidRule::DistinctOnRight(x)));
+        assert_eq!(validate(&rule("h $x $$x -> a", &mut symbols)), Err(InvalidRule::MixedVariableKinds(x)));
+        assert_eq!(validate(&rule("pos $x -> yes where $x !~ (neg $z)", &mut symbols)), Ok(()));
+        // Valid, but only useful under a strategy
+        let everything = rule("$x -> a", &mut symbols);
+        assert_eq!(validate(&everything), Ok(()));
+        assert!(matches_everything(&everything));
+    }
+
+}
@@ -0,0 +1,272 @@
+use wasm_bindgen::prelude::*;
+
+use crate::parse::ParseError;
+use crate::reduce::RewriteRule;
+use crate::symbol_table::SymbolTable;
+use crate::trs::{SourceError, Trs};
+
+#[wasm_bindgen]
+pub struct TrsHandle {
+    trs: Trs
+}
+
+/// Load the rules in `src`, throwing the first line that could not be
+/// parsed. Use `trs_init("")` and `add_rules` to get every error instead.
+#[wasm_bindgen]
+pub fn trs_init(src: &str) -> Result<TrsHandle, TrsError> {
+    console_error_panic_hook::set_once();
+    let mut trs = Trs::new();
+    if let Some(error) = diagnostics(trs.load(src), src).into_iter().next() {
+        return Err(error);
+    }
+    Ok(TrsHandle { trs })
+}
+
+/// Apply the rewrite `reduce_once` chooses to the term `s`.
+#[wasm_bindgen]
+pub fn trs_reduce_once(s: &str, trs: &mut TrsHandle) -> Result<String, TrsError> {
+    let trs = &mut trs.trs;
+    let mut expr = trs.parse(s).map_err(|err| TrsError::parse(err, s))?;
+    expr.reduce_once(trs.rules(), trs.symbols());
+    Ok(expr.bind(trs.symbols()).to_string())
+}
+
+/// Thrown to JavaScript when a term cannot be parsed, and returned
+/// as a diagnostic for each rule that could not be parsed.
+/// Lines and columns start from 1, columns count UTF-16 code units.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct TrsError {
+    pub message: String,
+    pub line: u32,
+    pub column: u32
+}
+
+impl TrsError {
+
+    /// An error in the term `src`.
+    fn parse(err: ParseError, src: &str) -> Self {
+        TrsError {
+            message: format!("{}", err.kind()),
+            line: 1,
+            column: err.utf16_column(src) as u32 + 1
+        }
+    }
+
+    /// An error on a line of the rule file `src`.
+    fn source(err: SourceError, src: &str) -> Self {
+        let text = src.lines().nth(err.line).unwrap_or("");
+        TrsError {
+            message: format!("{}", err.error.kind()),
+            line: err.line as u32 + 1,
+            column: err.error.utf16_column(text) as u32 + 1
+        }
+    }
+
+}
+
+fn diagnostics(result: Result<(), Vec<SourceError>>, src: &str) -> Vec<TrsError> {
+    match result {
+        Ok(()) => vec![],
+        Err(errors) => errors.into_iter().map(|err| TrsError::source(err, src)).collect()
+    }
+}
+
+/// The result of `TrsHandle.normalize`
+#[wasm_bindgen(getter_with_clone)]
+pub struct NormalForm {
+    pub term: String,
+    pub steps: u32,
+    /// False if normalization stopped at the step limit
+    pub complete: bool
+}
+
+/// One rewrite in the result of `TrsHandle.trace`
+#[wasm_bindgen(getter_with_clone)]
+pub struct TraceStep {
+    pub label: Option<String>,
+    pub rule: String,
+    pub position: Vec<u32>,
+    pub term: String
+}
+
+#[wasm_bindgen(getter_with_clone)]
+pub struct Rule {
+    pub label: Option<String>,
+    pub left: String,
+    pub right: String
+}
+
+/// A place where a rule applies, as found by `TrsHandle.find_redexes`
+#[wasm_bindgen(getter_with_clone)]
+pub struct Redex {
+    pub label: Option<String>,
+    pub rule: String,
+    pub position: Vec<u32>,
+    /// The whole term after applying this rewrite
+    pub result: String
+}
+
+/// `trace` gives up after this many steps
+const MAX_TRACE_STEPS: usize = 10_000;
+
+fn rule_text(rule: &RewriteRule, symbols: &SymbolTable) -> String {
+    format!("{} -> {}", rule.left.bind(symbols), rule.right.bind(symbols))
+}
+
+fn label(rule: &RewriteRule, symbols: &SymbolTable) -> Option<String> {
+    rule.label.map(|l| symbols.lookup(l).to_owned())
+}
+
+fn positions(position: &[usize]) -> Vec<u32> {
+    position.iter().map(|idx| *idx as u32).collect()
+}
+
+#[wasm_bindgen]
+impl TrsHandle {
+
+    /// Add the rules in `src` to the existing ones.
+    /// Returns an error for each line that could not be parsed,
+    /// the remaining lines are still added.
+    pub fn add_rules(&mut self, src: &str) -> Vec<TrsError> {
+        diagnostics(self.trs.load(src), src)
+    }
+
+    /// Remove every rule labeled `label`, returning how many were removed.
+    pub fn remove_rule(&mut self, label: &str) -> u32 {
+        self.trs.remove_rule(label) as u32
+    }
+
+    /// Replace all rules with those in `src`, keeping interned symbols.
+    /// Returns an error for each line that could not be parsed.
+    pub fn replace_source(&mut self, src: &str) -> Vec<TrsError> {
+        diagnostics(self.trs.replace_source(src), src)
+    }
+
+    /// An independent copy of this handle that can be edited separately.
+    pub fn clone_handle(&self) -> TrsHandle {
+        TrsHandle { trs: self.trs.clone() }
+    }
+
+    /// Reduce `expr` until it reaches a normal form or `max_steps` rewrites were applied.
+    pub fn normalize(&mut self, expr: &str, max_steps: u32) -> Result<NormalForm, TrsError> {
+        let derivation = self.trs.derive(expr, max_steps as usize).map_err(|err| TrsError::parse(err, expr))?;
+        Ok(NormalForm {
+            term: derivation.result().bind(self.trs.symbols()).to_string(),
+            steps: derivation.steps.len() as u32,
+            complete: !derivation.truncated
+        })
+    }
+
+    /// Every step taken while normalizing `expr`.
+    pub fn trace(&mut self, expr: &str) -> Result<Vec<TraceStep>, TrsError> {
+        let derivation = self.trs.derive(expr, MAX_TRACE_STEPS).map_err(|err| TrsError::parse(err, expr))?;
+        let trs = &self.trs;
+        let symbols = trs.symbols();
+        Ok(derivation.steps.iter()
+            .map(|step| {
+                let rule = trs.rules().get(step.rule);
+                TraceStep {
+                    label: label(rule, symbols),
+                    rule: rule_text(rule, symbols),
+                    position: positions(&step.position),
+                    term: step.term.bind(symbols).to_string()
+                }
+            })
+            .collect())
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        let trs = &self.trs;
+        let symbols = trs.symbols();
+        trs.rules().iter()
+            .map(|rule| Rule {
+                label: label(rule, symbols),
+                left: rule.left.bind(symbols).to_string(),
+                right: rule.right.bind(symbols).to_string()
+            })
+            .collect()
+    }
+
+    /// Every place in `expr` where a rule applies, in the order they would be reduced.
+    pub fn find_redexes(&mut self, expr: &str) -> Result<Vec<Redex>, TrsError> {
+        let (term, redexes) = self.trs.find_redexes(expr).map_err(|err| TrsError::parse(err, expr))?;
+        let trs = &self.trs;
+        let symbols = trs.symbols();
+        Ok(redexes.iter()
+            .map(|rewrite| {
+                let rule = trs.rules().get(rewrite.rule);
+                let mut result = term.clone();
+                result.apply(rewrite, trs.rules(), symbols);
+                Redex {
+                    label: label(rule, symbols),
+                    rule: rule_text(rule, symbols),
+                    position: positions(&rewrite.position),
+                    result: result.bind(symbols).to_string()
+                }
+            })
+            .collect())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut trs = trs_init("[define-one] 1 -> S 0\n$x + 0 -> $x\n$x + (S $y) -> S ($x + $y)").unwrap();
+        let result = trs.normalize("1 + 1", 100).unwrap();
+        assert_eq!(result.term, "S (S 0)");
+        assert!(result.complete);
+        assert!(!trs.normalize("1 + 1", 1).unwrap().complete);
+    }
+
+    #[test]
+    fn test_trace_labels() {
+        let mut trs = trs_init("[define-one] 1 -> S 0").unwrap();
+        let steps = trs.trace("S 1").unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].label.as_deref(), Some("define-one"));
+        assert_eq!(steps[0].position, vec![1]);
+    }
+
+    #[test]
+    fn test_incremental_edits() {
+        let mut trs = trs_init("[define-one] 1 -> S 0").unwrap();
+        let errors = trs.add_rules("[define-two] 2 -> S (S 0)\nnot a rule");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(trs.rules().len(), 2);
+
+        let mut copy = trs.clone_handle();
+        assert_eq!(trs.remove_rule("define-one"), 1);
+        assert_eq!(trs.normalize("1", 10).unwrap().term, "1");
+        assert_eq!(copy.normalize("1", 10).unwrap().term, "S 0");
+
+        assert!(trs.replace_source("1 -> one").is_empty());
+        assert_eq!(trs.normalize("2 1", 10).unwrap().term, "2 one");
+    }
+
+    #[test]
+    fn test_parse_error_column() {
+        let mut trs = trs_init("").unwrap();
+        let err = trs.normalize("a b ) c", 10).err().unwrap();
+        assert_eq!((err.line, err.column), (1, 5));
+        // Columns count UTF-16 code units, not bytes
+        let err = trs.normalize("λ → ) c", 10).err().unwrap();
+        assert_eq!((err.line, err.column), (1, 5));
+    }
+
+    #[test]
+    fn test_init_errors() {
+        let err = trs_init("1 -> S 0\nλ $x -> )").err().unwrap();
+        assert_eq!((err.line, err.column), (2, 9));
+        let mut trs = trs_init("[define-one] 1 -> S 0").unwrap();
+        assert_eq!(trs_reduce_once("S 1", &mut trs).unwrap(), "S (S 0)");
+        assert_eq!(trs_reduce_once("S )", &mut trs).err().unwrap().column, 3);
+    }
+
+}